@@ -0,0 +1,211 @@
+//! A record of configuration changes, kept newest first in the capped
+//! `audit:{guild}` Redis list and posted to the config changes log.
+
+use deadpool_redis::{
+    redis::{AsyncCommands, RedisError},
+    Connection,
+};
+use serde::{Deserialize, Serialize};
+use twilight_http::Client;
+use twilight_model::id::{
+    marker::{GuildMarker, UserMarker},
+    Id,
+};
+use twilight_util::builder::embed::{EmbedBuilder, EmbedFieldBuilder};
+
+use crate::{
+    config::{ConfigField, GuildConfig, LogCategory},
+    logger::{self, Logger},
+};
+
+/// How many changes are kept per guild, older ones are dropped.
+pub const MAX_ENTRIES: usize = 200;
+
+/// Changes shown per page of `/logging history`.
+pub const PAGE_SIZE: usize = 10;
+
+const COLOUR_CHANGED: u32 = 0xFEE75C;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error("Failed to read or write the audit log.")]
+    RedisFailed(#[from] RedisError),
+
+    #[error("Failed to serialize an audit log entry.")]
+    JsonFailed(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub user_id: Id<UserMarker>,
+    /// Unix timestamp in seconds.
+    pub timestamp: u64,
+    /// The [`ConfigField::key`] that was changed.
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl AuditEntry {
+    /// The old and new value, e.g. `*unset* → <@&2>`.
+    pub fn change(&self) -> String {
+        format!(
+            "{} → {}",
+            describe(&self.field, self.old.as_deref()),
+            describe(&self.field, self.new.as_deref()),
+        )
+    }
+
+    /// One line for `/logging history`.
+    pub fn summary(&self) -> String {
+        format!(
+            "<t:{}:R> <@{}> changed `{}`: {}",
+            self.timestamp,
+            self.user_id,
+            self.field,
+            self.change(),
+        )
+    }
+}
+
+fn key(guild_id: Id<GuildMarker>) -> String {
+    format!("audit:{}", guild_id.get())
+}
+
+/// Shows ids as mentions so the log stays readable.
+fn describe(field: &str, value: Option<&str>) -> String {
+    let value = match value {
+        Some(value) => value,
+        None => return String::from("*unset*"),
+    };
+
+    match ConfigField::from_key(field) {
+        Some(
+            ConfigField::VerificationRole
+            | ConfigField::UnverifiedRole
+            | ConfigField::QuarantineRole,
+        ) => {
+            format!("<@&{}>", value)
+        }
+        Some(
+            ConfigField::GateChannel | ConfigField::LoggingChannel | ConfigField::LogChannel(_),
+        ) => format!("<#{}>", value),
+        _ => format!("`{}`", value),
+    }
+}
+
+/// Makes config changes on behalf of a member, recording each one.
+pub struct AuditLog<'a> {
+    http: &'a Client,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+}
+
+impl<'a> AuditLog<'a> {
+    pub fn new(http: &'a Client, guild_id: Id<GuildMarker>, user_id: Id<UserMarker>) -> Self {
+        Self {
+            http,
+            guild_id,
+            user_id,
+        }
+    }
+
+    /// Sets a field like [`GuildConfig::patch`] and records the change when
+    /// the value actually changed.
+    pub async fn patch(
+        &self,
+        conn: &mut Connection,
+        field: ConfigField,
+        value: Option<String>,
+    ) -> Result<(), AuditError> {
+        let old = GuildConfig::patch(conn, self.guild_id, field, value.clone()).await?;
+        if old == value {
+            return Ok(());
+        }
+
+        self.record(
+            conn,
+            AuditEntry {
+                user_id: self.user_id,
+                timestamp: logger::now(),
+                field: field.key().to_string(),
+                old,
+                new: value,
+            },
+        )
+        .await
+    }
+
+    /// Stores the entry and posts it to the config changes log. The change
+    /// has already been made at this point, so failing to post is only traced.
+    async fn record(&self, conn: &mut Connection, entry: AuditEntry) -> Result<(), AuditError> {
+        deadpool_redis::redis::pipe()
+            .atomic()
+            .lpush(key(self.guild_id), serde_json::to_string(&entry)?)
+            .ignore()
+            .ltrim(key(self.guild_id), 0, MAX_ENTRIES as isize - 1)
+            .ignore()
+            .query_async::<_, ()>(conn)
+            .await?;
+
+        let embed = EmbedBuilder::new()
+            .title("Config changed")
+            .color(COLOUR_CHANGED)
+            .description(entry.change())
+            .field(EmbedFieldBuilder::new("Changed by", format!("<@{}>", entry.user_id)).inline())
+            .field(EmbedFieldBuilder::new("Field", format!("`{}`", entry.field)).inline())
+            .build();
+        if let Err(why) = Logger::new(self.http)
+            .post(conn, self.guild_id, LogCategory::ConfigChanges, embed)
+            .await
+        {
+            tracing::warn!(guild = self.guild_id.get(), error = ?why, "failed to log config change");
+        }
+
+        Ok(())
+    }
+}
+
+/// A page of changes, newest first, and how many changes there are in total.
+/// Pages start at `0`. With `field` set only changes to that field are
+/// counted and returned.
+pub async fn history(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    field: Option<&str>,
+    page: usize,
+) -> Result<(Vec<AuditEntry>, usize), AuditError> {
+    // Past `MAX_ENTRIES` every page is empty, capping keeps the range in bounds.
+    let start = page.saturating_mul(PAGE_SIZE).min(MAX_ENTRIES);
+    if let Some(field) = field {
+        // There are at most `MAX_ENTRIES`, so filtering them here is cheap.
+        let entries: Vec<String> = conn.lrange(key(guild_id), 0, -1).await?;
+        let matching: Vec<AuditEntry> = entries
+            .iter()
+            .filter_map(|entry| serde_json::from_str::<AuditEntry>(entry).ok())
+            .filter(|entry| entry.field == field)
+            .collect();
+        let total = matching.len();
+        return Ok((
+            matching.into_iter().skip(start).take(PAGE_SIZE).collect(),
+            total,
+        ));
+    }
+    let (entries, total): (Vec<String>, usize) = deadpool_redis::redis::pipe()
+        .lrange(
+            key(guild_id),
+            start as isize,
+            (start + PAGE_SIZE) as isize - 1,
+        )
+        .llen(key(guild_id))
+        .query_async(conn)
+        .await?;
+
+    // Entries that no longer deserialize are skipped rather than failing the
+    // whole page.
+    let entries = entries
+        .iter()
+        .filter_map(|entry| serde_json::from_str(entry).ok())
+        .collect();
+    Ok((entries, total))
+}
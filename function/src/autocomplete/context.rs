@@ -1,6 +1,15 @@
-use twilight_model::application::interaction::ApplicationCommand;
+use twilight_model::application::interaction::ApplicationCommandAutocomplete;
 
-pub struct AutoCompleteContext {
+use crate::Context;
+
+pub struct AutoCompleteContext<'a> {
     // pub(crate) http: &'a mut Http,
-    pub(crate) command: ApplicationCommand,
+    pub data: &'a Context,
+    /// The whole interaction, for providers that depend on the guild or on
+    /// other options.
+    pub command: ApplicationCommandAutocomplete,
+    /// Name of the option the user is typing in.
+    pub option: String,
+    /// What the user has typed so far.
+    pub value: String,
 }
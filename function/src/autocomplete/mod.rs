@@ -1,39 +1,260 @@
-use crate::CustomError;
+use std::collections::HashMap;
+
+use crate::{
+    config::{ConfigField, VerificationType},
+    path::CommandPath,
+    CustomError,
+};
+use futures::future::BoxFuture;
 use twilight_model::{
-    application::{command::CommandOptionChoice, interaction::ApplicationCommandAutocomplete},
+    application::{
+        command::CommandOptionChoice,
+        interaction::{
+            application_command_autocomplete::ApplicationCommandAutocompleteDataOption,
+            ApplicationCommandAutocomplete,
+        },
+    },
     http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType},
 };
 mod context;
 
-pub async fn handle(
-    _data: ApplicationCommandAutocomplete,
-) -> Result<InteractionResponse, CustomError> {
-    Ok(InteractionResponse {
-        kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
-        data: Some(InteractionResponseData {
-            choices: Some(vec![
-                CommandOptionChoice::String {
-                    name: "A".to_string(),
-                    name_localizations: None,
-                    value: "A".to_string(),
-                },
-                CommandOptionChoice::String {
-                    name: "B".to_string(),
-                    name_localizations: None,
-                    value: "B".to_string(),
-                },
-                CommandOptionChoice::String {
-                    name: "C".to_string(),
-                    name_localizations: None,
-                    value: "C".to_string(),
-                },
-                CommandOptionChoice::String {
-                    name: "D".to_string(),
-                    name_localizations: None,
-                    value: "D".to_string(),
-                },
-            ]),
-            ..Default::default()
-        }),
+pub use context::AutoCompleteContext;
+
+/// Discord rejects autocomplete results with more choices than this.
+pub const MAX_CHOICES: usize = 25;
+
+pub type AutocompleteResult =
+    Result<Vec<CommandOptionChoice>, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Suggests values for one option of one command.
+pub type AutocompleteFn =
+    for<'a> fn(&'a AutoCompleteContext<'a>) -> BoxFuture<'a, AutocompleteResult>;
+
+/// Routes autocomplete interactions to providers by command path and option
+/// name, e.g. `("verification type", "choice")`. Options with a provider are
+/// registered with `autocomplete` set, see [`crate::registration`].
+pub struct AutocompleteRouter {
+    providers: HashMap<(&'static str, &'static str), AutocompleteFn>,
+}
+
+impl AutocompleteRouter {
+    pub fn new() -> Self {
+        Self {
+            providers: HashMap::new(),
+        }
+    }
+
+    /// Registers a provider. `command` is the space separated path to the
+    /// command, including its group and subcommand group.
+    pub fn provider(
+        mut self,
+        command: &'static str,
+        option: &'static str,
+        fun: AutocompleteFn,
+    ) -> Self {
+        self.providers.insert((command, option), fun);
+        self
+    }
+
+    pub fn has_provider(&self, command: &str, option: &str) -> bool {
+        self.providers
+            .keys()
+            .any(|(c, o)| *c == command && *o == option)
+    }
+
+    pub async fn handle(
+        &self,
+        data: &crate::Context,
+        command: ApplicationCommandAutocomplete,
+    ) -> Result<InteractionResponse, CustomError> {
+        let (path, focused) = resolve(&command.data.name, &command.data.options);
+
+        let mut choices = match focused.and_then(|(option, value)| {
+            self.providers
+                .iter()
+                .find(|((c, o), _)| *c == path && *o == option)
+                .map(|(_, fun)| (*fun, option, value))
+        }) {
+            Some((fun, option, value)) => {
+                let context = AutoCompleteContext {
+                    data,
+                    command,
+                    option,
+                    value,
+                };
+                match fun(&context).await {
+                    Ok(choices) => choices,
+                    Err(why) => {
+                        tracing::warn!(command = %path, "autocomplete provider failed: {}", why);
+                        Vec::new()
+                    }
+                }
+            }
+            None => Vec::new(),
+        };
+        choices.truncate(MAX_CHOICES);
+
+        Ok(InteractionResponse {
+            kind: InteractionResponseType::ApplicationCommandAutocompleteResult,
+            data: Some(InteractionResponseData {
+                choices: Some(choices),
+                ..Default::default()
+            }),
+        })
+    }
+}
+
+impl Default for AutocompleteRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The command path, resolved like commands are, and the focused option with
+/// its value.
+fn resolve(
+    name: &str,
+    options: &[ApplicationCommandAutocompleteDataOption],
+) -> (String, Option<(String, String)>) {
+    let path = CommandPath::resolve(name, options);
+    let focused = path
+        .options
+        .iter()
+        .find(|o| o.focused)
+        .map(|o| (o.name.clone(), o.value.clone().unwrap_or_default()));
+
+    (path.to_string(), focused)
+}
+
+/// Turns `values` into choices, keeping those containing what was typed.
+pub fn filter_choices<I, S>(values: I, typed: &str) -> Vec<CommandOptionChoice>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let typed = typed.to_lowercase();
+    values
+        .into_iter()
+        .map(Into::into)
+        .filter(|value: &String| value.to_lowercase().contains(&typed))
+        .map(|value| CommandOptionChoice::String {
+            name: value.clone(),
+            name_localizations: None,
+            value,
+        })
+        .collect()
+}
+
+pub fn verification_types<'a>(
+    ctx: &'a AutoCompleteContext<'a>,
+) -> BoxFuture<'a, AutocompleteResult> {
+    Box::pin(async move {
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(filter_choices(
+            VerificationType::ALL.iter().map(ToString::to_string),
+            &ctx.value,
+        ))
+    })
+}
+
+/// Suggests the config fields recorded in the audit log.
+pub fn config_fields<'a>(ctx: &'a AutoCompleteContext<'a>) -> BoxFuture<'a, AutocompleteResult> {
+    Box::pin(async move {
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(filter_choices(
+            ConfigField::ALL.iter().map(|field| field.key()),
+            &ctx.value,
+        ))
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use twilight_model::application::command::CommandOptionType;
+
+    use super::*;
+
+    fn option(
+        kind: CommandOptionType,
+        name: &str,
+        options: Vec<ApplicationCommandAutocompleteDataOption>,
+    ) -> ApplicationCommandAutocompleteDataOption {
+        ApplicationCommandAutocompleteDataOption {
+            focused: false,
+            kind,
+            name: name.to_string(),
+            options,
+            value: None,
+        }
+    }
+
+    fn focused(name: &str, value: &str) -> ApplicationCommandAutocompleteDataOption {
+        ApplicationCommandAutocompleteDataOption {
+            focused: true,
+            value: Some(value.to_string()),
+            ..option(CommandOptionType::String, name, Vec::new())
+        }
+    }
+
+    #[test]
+    fn resolves_subcommand_path() {
+        let options = vec![option(
+            CommandOptionType::SubCommand,
+            "type",
+            vec![focused("choice", "cap")],
+        )];
+
+        assert_eq!(
+            resolve("verification", &options),
+            (
+                String::from("verification type"),
+                Some((String::from("choice"), String::from("cap")))
+            )
+        );
+    }
+
+    #[test]
+    fn resolves_subcommand_group_path() {
+        let options = vec![option(
+            CommandOptionType::SubCommandGroup,
+            "roles",
+            vec![option(
+                CommandOptionType::SubCommand,
+                "add",
+                vec![
+                    option(CommandOptionType::Role, "role", Vec::new()),
+                    focused("name", ""),
+                ],
+            )],
+        )];
+
+        let (path, focused) = resolve("admin", &options);
+        assert_eq!(path, "admin roles add");
+        assert_eq!(focused, Some((String::from("name"), String::new())));
+    }
+
+    #[test]
+    fn resolves_without_focused_option() {
+        let options = vec![option(CommandOptionType::String, "choice", Vec::new())];
+
+        assert_eq!(resolve("type", &options), (String::from("type"), None));
+    }
+
+    #[test]
+    fn filters_case_insensitively() {
+        let choices = filter_choices(["None", "Captcha", "Audio"], "CAP");
+
+        assert_eq!(
+            choices,
+            vec![CommandOptionChoice::String {
+                name: String::from("Captcha"),
+                name_localizations: None,
+                value: String::from("Captcha"),
+            }]
+        );
+    }
+
+    #[test]
+    fn empty_input_keeps_everything() {
+        assert_eq!(filter_choices(["a", "b", "c"], "").len(), 3);
+        assert!(filter_choices(["a", "b", "c"], "z").is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+//! Registers the application commands built by `function::framework`.
+//!
+//! ```text
+//! register-commands [--dry-run] [--global] [--guild <id>]...
+//! register-commands export
+//! ```
+//!
+//! Without `--global` or `--guild` the scopes are read from `REGISTER_GLOBAL`
+//! and `DEV_GUILDS`. Each scope is diffed against what Discord has and only
+//! overwritten when they differ.
+//!
+//! `export` prints the command tree as canonical JSON without talking to
+//! Discord, for checking in next to the code and reviewing in PRs.
+
+use std::{env, process, sync::Arc};
+
+use function::{
+    registration::{self, Scope, TreeDiff},
+    Context,
+};
+use twilight_http::Client;
+use twilight_model::id::Id;
+
+const USAGE: &str = "usage: register-commands [--dry-run] [--global] [--guild <id>]...\n       register-commands export";
+
+struct Args {
+    export: bool,
+    dry_run: bool,
+    scopes: Vec<Scope>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        export: false,
+        dry_run: false,
+        scopes: Vec::new(),
+    };
+
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "export" => args.export = true,
+            "--dry-run" => args.dry_run = true,
+            "--global" => args.scopes.push(Scope::Global),
+            "--guild" => {
+                let id = iter
+                    .next()
+                    .and_then(|id| id.parse().ok())
+                    .and_then(Id::new_checked)
+                    .ok_or("--guild expects a guild id")?;
+                args.scopes.push(Scope::Guild(id));
+            }
+            other => return Err(format!("unknown argument `{}`", other)),
+        }
+    }
+
+    if args.export && (args.dry_run || !args.scopes.is_empty()) {
+        return Err(String::from("export takes no other arguments"));
+    }
+    if !args.export && args.scopes.is_empty() {
+        args.scopes = registration::scopes_from_env();
+    }
+    Ok(args)
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(why) => {
+            eprintln!("{}\n{}", why, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if args.export {
+        // Neither the token, Redis nor the bot id are used to build the tree.
+        let framework = function::framework(
+            Arc::new(Client::new(String::new())),
+            Id::new(1),
+            Context::new("redis://localhost:6379", Id::new(1)),
+        );
+        print!(
+            "{}",
+            registration::export(&framework, &function::components().autocomplete)
+        );
+        return;
+    }
+
+    if args.scopes.is_empty() {
+        eprintln!(
+            "No scopes given, pass --global or --guild, or set REGISTER_GLOBAL or DEV_GUILDS"
+        );
+        process::exit(2);
+    }
+
+    let token = env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN is not set");
+    let application_id = Id::new(
+        env::var("APPLICATION_ID")
+            .expect("APPLICATION_ID is not set")
+            .parse()
+            .expect("APPLICATION_ID is not a valid id"),
+    );
+    let redis_url = env::var("REDIS_URL").unwrap_or("redis://localhost:6379".to_string());
+
+    let http_client = Arc::new(Client::builder().token(token).build());
+    // Commands aren't run here, so the bot id is never read.
+    let framework = function::framework(
+        Arc::clone(&http_client),
+        application_id,
+        Context::new(redis_url, Id::new(1)),
+    );
+    let local = registration::command_tree(&framework, &function::components().autocomplete);
+
+    let mut failed = false;
+    for scope in args.scopes {
+        let remote = match registration::remote_tree(&http_client, application_id, scope).await {
+            Ok(remote) => remote,
+            Err(why) => {
+                eprintln!("Failed to fetch {} commands: {}", scope, why);
+                failed = true;
+                continue;
+            }
+        };
+
+        let diff = TreeDiff::new(&remote, &local);
+        if diff.is_empty() {
+            println!("{} commands are up to date", scope);
+            continue;
+        }
+        println!("{} commands differ:\n{}", scope, diff);
+
+        if args.dry_run {
+            continue;
+        }
+        match registration::apply(&http_client, application_id, &local, scope).await {
+            Ok(()) => println!("Registered {} commands", scope),
+            Err(why) => {
+                eprintln!("Failed to register {} commands: {}", scope, why);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
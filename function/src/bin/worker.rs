@@ -0,0 +1,112 @@
+//! Subscribes to the gateway events `twilight-dispatch` publishes to Redis and
+//! starts verification for members as they join. Also applies the timeout
+//! action to members whose verification deadline passed.
+//!
+//! Reads `DISCORD_TOKEN`, `REDIS_URL`, `DISPATCH_CHANNEL` (default `gateway`)
+//! and `BOT_ID` (fetched from Discord when unset). Runs until killed,
+//! reconnecting when Redis goes away.
+
+use std::{env, sync::Arc, time::Duration};
+
+use deadpool_redis::redis::{self, RedisError};
+use function::{
+    context,
+    events::{self, EventHandler},
+    Context,
+};
+use futures::StreamExt;
+use twilight_http::Client;
+
+/// Upper bound for the delay between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How often expired verification deadlines are looked for.
+const TIMEOUT_POLL: Duration = Duration::from_secs(15);
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .init();
+
+    let token = env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN is not set");
+    let redis_url = env::var("REDIS_URL").unwrap_or("redis://localhost:6379".to_string());
+    let channel = env::var("DISPATCH_CHANNEL").unwrap_or(events::DEFAULT_CHANNEL.to_string());
+
+    let client = redis::Client::open(redis_url.as_str()).expect("REDIS_URL is not a valid url");
+    let http = Arc::new(Client::builder().token(token).build());
+    let bot_id = match context::env_bot_id() {
+        Some(bot_id) => bot_id,
+        None => context::current_user_id(&http)
+            .await
+            .expect("Failed to fetch the bot user"),
+    };
+    let handler = Arc::new(EventHandler::new(http, Context::new(redis_url, bot_id)));
+
+    tokio::spawn(process_timeouts(Arc::clone(&handler)));
+
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        match subscribe(&client, &channel, &handler, &mut backoff).await {
+            Ok(()) => tracing::warn!("subscription to {} ended", channel),
+            Err(why) => tracing::error!(error = ?why, "subscription to {} failed", channel),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Handles messages until the connection drops. Every event is handled on its
+/// own task so a slow Discord request doesn't hold up the others.
+async fn subscribe(
+    client: &redis::Client,
+    channel: &str,
+    handler: &Arc<EventHandler>,
+    backoff: &mut Duration,
+) -> Result<(), RedisError> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(channel).await?;
+    tracing::info!("subscribed to {}", channel);
+    *backoff = Duration::from_secs(1);
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = match message.get_payload() {
+            Ok(payload) => payload,
+            Err(why) => {
+                tracing::warn!(error = ?why, "unreadable message");
+                continue;
+            }
+        };
+
+        let event = match events::parse(&payload) {
+            Ok(Some(event)) => event,
+            Ok(None) => continue,
+            Err(why) => {
+                tracing::warn!(error = ?why, "failed to deserialize event");
+                continue;
+            }
+        };
+
+        let handler = Arc::clone(handler);
+        tokio::spawn(async move {
+            if let Err(why) = handler.handle(event).await {
+                tracing::error!(error = ?why, "failed to handle event");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn process_timeouts(handler: Arc<EventHandler>) {
+    let mut interval = tokio::time::interval(TIMEOUT_POLL);
+    loop {
+        interval.tick().await;
+        match handler.process_timeouts().await {
+            Ok(0) => {}
+            Ok(handled) => tracing::info!("applied {} verification timeouts", handled),
+            Err(why) => tracing::error!(error = ?why, "failed to process verification timeouts"),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Read access to the guild cache `twilight-dispatch` keeps in Redis.
+
+use deadpool_redis::{
+    redis::{AsyncCommands, RedisError},
+    Connection,
+};
+use serde::Deserialize;
+use twilight_model::{
+    guild::{Permissions, Role},
+    id::{
+        marker::{GuildMarker, RoleMarker, UserMarker},
+        Id,
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("Failed to read from the cache.")]
+    RedisFailed(#[from] RedisError),
+
+    #[error("Failed to deserialize a cached entry.")]
+    JsonFailed(#[from] serde_json::Error),
+}
+
+#[derive(Deserialize)]
+struct CachedMember {
+    #[serde(default)]
+    roles: Vec<Id<RoleMarker>>,
+}
+
+pub async fn role(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    role_id: Id<RoleMarker>,
+) -> Result<Option<Role>, CacheError> {
+    let data: Option<String> = conn
+        .get(format!("role:{}:{}", guild_id.get(), role_id.get()))
+        .await?;
+
+    Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+}
+
+/// The roles of a member, not including `@everyone`.
+pub async fn member_roles(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<Option<Vec<Id<RoleMarker>>>, CacheError> {
+    let data: Option<String> = conn
+        .get(format!("member:{}:{}", guild_id.get(), user_id.get()))
+        .await?;
+
+    Ok(data
+        .map(|data| serde_json::from_str::<CachedMember>(&data))
+        .transpose()?
+        .map(|member| member.roles))
+}
+
+/// Position of the member's highest role, `0` when they only have `@everyone`.
+pub async fn highest_position(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<Option<i64>, CacheError> {
+    let roles = match member_roles(conn, guild_id, user_id).await? {
+        Some(roles) => roles,
+        None => return Ok(None),
+    };
+
+    let mut highest = 0;
+    for role_id in roles {
+        if let Some(role) = role(conn, guild_id, role_id).await? {
+            highest = highest.max(role.position);
+        }
+    }
+
+    Ok(Some(highest))
+}
+
+/// Guild level permissions of a member, `None` when they aren't cached.
+pub async fn permissions(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<Option<Permissions>, CacheError> {
+    let roles = match member_roles(conn, guild_id, user_id).await? {
+        Some(roles) => roles,
+        None => return Ok(None),
+    };
+
+    // `@everyone` shares its id with the guild.
+    let mut permissions = Permissions::empty();
+    for role_id in roles.into_iter().chain([guild_id.cast()]) {
+        if let Some(role) = role(conn, guild_id, role_id).await? {
+            permissions |= role.permissions;
+        }
+    }
+
+    Ok(Some(permissions))
+}
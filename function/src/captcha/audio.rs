@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    env,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use lazy_static::lazy_static;
+use rand::Rng;
+use twilight_model::http::attachment::Attachment;
+
+use super::{random_answer, CaptchaError, CaptchaOptions};
+
+/// Characters used for audio challenges, letters are too easy to mishear.
+pub const AUDIO_CHARSET: &str = "0123456789";
+
+/// Filename the clip is attached under.
+pub const AUDIO_FILENAME: &str = "captcha.wav";
+
+/// The digit clips bundled with the binary, so audio challenges work no
+/// matter where the function is started from.
+const BUNDLED: [(char, &[u8]); 10] = [
+    ('0', include_bytes!("../../assets/audio/0.wav")),
+    ('1', include_bytes!("../../assets/audio/1.wav")),
+    ('2', include_bytes!("../../assets/audio/2.wav")),
+    ('3', include_bytes!("../../assets/audio/3.wav")),
+    ('4', include_bytes!("../../assets/audio/4.wav")),
+    ('5', include_bytes!("../../assets/audio/5.wav")),
+    ('6', include_bytes!("../../assets/audio/6.wav")),
+    ('7', include_bytes!("../../assets/audio/7.wav")),
+    ('8', include_bytes!("../../assets/audio/8.wav")),
+    ('9', include_bytes!("../../assets/audio/9.wav")),
+];
+
+lazy_static! {
+    /// The bundled clips, or the ones in `CAPTCHA_SAMPLE_DIR` when it is set.
+    static ref SAMPLES: Result<SampleBank, String> = match env::var_os("CAPTCHA_SAMPLE_DIR") {
+        Some(dir) => SampleBank::load(Path::new(&dir)),
+        None => SampleBank::bundled(),
+    }
+    .map_err(|why| why.to_string());
+}
+
+struct SampleBank {
+    spec: WavSpec,
+    clips: HashMap<char, Vec<i16>>,
+}
+
+impl SampleBank {
+    fn bundled() -> Result<Self, CaptchaError> {
+        let mut bank = Self::empty();
+        for (c, bytes) in BUNDLED {
+            bank.insert(c, WavReader::new(Cursor::new(bytes))?)?;
+        }
+        bank.finish()
+    }
+
+    /// Loads every `<char>.wav` in `dir`.
+    fn load(dir: &Path) -> Result<Self, CaptchaError> {
+        let mut bank = Self::empty();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let c = match (
+                path.extension().and_then(|e| e.to_str()),
+                path.file_stem().and_then(|s| s.to_str()),
+            ) {
+                (Some("wav"), Some(stem)) if stem.chars().count() == 1 => {
+                    stem.chars().next().unwrap().to_ascii_uppercase()
+                }
+                _ => continue,
+            };
+            bank.insert(c, WavReader::open(&path)?)?;
+        }
+        bank.finish()
+    }
+
+    fn empty() -> Self {
+        Self {
+            spec: WavSpec {
+                channels: 0,
+                sample_rate: 0,
+                bits_per_sample: 0,
+                sample_format: SampleFormat::Int,
+            },
+            clips: HashMap::new(),
+        }
+    }
+
+    /// Adds a clip. All clips must be 16-bit PCM with the same sample rate
+    /// and channel count.
+    fn insert<R: Read>(&mut self, c: char, reader: WavReader<R>) -> Result<(), CaptchaError> {
+        let spec = reader.spec();
+        if spec.sample_format != SampleFormat::Int || spec.bits_per_sample != 16 {
+            return Err(CaptchaError::InvalidSample(c));
+        }
+        if self.clips.is_empty() {
+            self.spec = spec;
+        } else if self.spec != spec {
+            return Err(CaptchaError::InvalidSample(c));
+        }
+
+        let samples = reader.into_samples::<i16>().collect::<Result<_, _>>()?;
+        self.clips.insert(c, samples);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Self, CaptchaError> {
+        if self.clips.is_empty() {
+            return Err(CaptchaError::NoSamples);
+        }
+        Ok(self)
+    }
+}
+
+pub struct AudioCaptcha {
+    pub answer: String,
+    pub wav: Vec<u8>,
+}
+
+impl AudioCaptcha {
+    /// Generates a clip reading out a random answer over background noise.
+    pub fn generate(options: &CaptchaOptions) -> Result<Self, CaptchaError> {
+        let bank = SAMPLES
+            .as_ref()
+            .map_err(|why| CaptchaError::SamplesUnavailable(why.clone()))?;
+        let answer = random_answer(options)?;
+        let wav = render(bank, &answer, options.noise)?;
+
+        Ok(Self { answer, wav })
+    }
+
+    /// The clip as an attachment that can be added to an interaction response.
+    pub fn attachment(&self, id: u64) -> Attachment {
+        Attachment::from_bytes(AUDIO_FILENAME.to_string(), self.wav.clone(), id)
+    }
+}
+
+fn render(bank: &SampleBank, answer: &str, noise: f32) -> Result<Vec<u8>, CaptchaError> {
+    let mut rng = rand::thread_rng();
+    let frame = bank.spec.channels as usize;
+    let rate = bank.spec.sample_rate as usize;
+
+    let mut samples: Vec<f32> = vec![0.0; rate / 2 * frame];
+    for c in answer.chars() {
+        let clip = bank
+            .clips
+            .get(&c.to_ascii_uppercase())
+            .ok_or(CaptchaError::UnsupportedCharacter(c))?;
+        let gain = rng.gen_range(0.7..=1.0);
+        samples.extend(clip.iter().map(|s| *s as f32 * gain));
+
+        // Uneven pauses make it harder to split the clip into characters.
+        let gap = rng.gen_range(rate / 4..=rate * 3 / 4);
+        samples.extend(std::iter::repeat(0.0).take(gap * frame));
+    }
+
+    let noise = noise.clamp(0.0, 1.0) * 4000.0;
+    let hum = rng.gen_range(50.0..=120.0) * 2.0 * std::f32::consts::PI / rate as f32;
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let t = (i / frame) as f32;
+        *sample += rng.gen_range(-noise..=noise) + (t * hum).sin() * noise * 0.5;
+    }
+
+    let mut wav = Vec::new();
+    let mut writer = WavWriter::new(Cursor::new(&mut wav), bank.spec)?;
+    for sample in samples {
+        writer.write_sample(sample.clamp(i16::MIN as f32, i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+
+    Ok(wav)
+}
@@ -0,0 +1,216 @@
+use deadpool_redis::{
+    redis::{AsyncCommands, RedisError},
+    Connection,
+};
+use image::ImageError;
+use rand::seq::SliceRandom;
+use twilight_model::{
+    http::attachment::Attachment,
+    id::{
+        marker::{GuildMarker, UserMarker},
+        Id,
+    },
+};
+
+mod audio;
+mod font;
+mod visual;
+
+pub use audio::{AudioCaptcha, AUDIO_CHARSET, AUDIO_FILENAME};
+
+/// Characters used when no charset is configured. Look-alikes such as `0`/`O`
+/// and `1`/`I` are left out on purpose.
+pub const DEFAULT_CHARSET: &str = "ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Filename the captcha image is attached under, reference it in embeds with
+/// `attachment://captcha.png`.
+pub const IMAGE_FILENAME: &str = "captcha.png";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CaptchaError {
+    #[error("The captcha charset is empty.")]
+    EmptyCharset,
+
+    #[error("The captcha font has no glyph for {0:?}.")]
+    UnsupportedCharacter(char),
+
+    #[error("Failed to encode the captcha image.")]
+    EncodeFailed(#[from] ImageError),
+
+    #[error("Invalid audio sample for {0:?}, clips must be 16-bit PCM in a single format.")]
+    InvalidSample(char),
+
+    #[error("No audio samples were found.")]
+    NoSamples,
+
+    #[error("Audio samples could not be loaded: {0}")]
+    SamplesUnavailable(String),
+
+    #[error("Failed to read audio samples.")]
+    ReadFailed(#[from] std::io::Error),
+
+    #[error("Failed to encode the captcha audio.")]
+    AudioFailed(#[from] hound::Error),
+
+    #[error("Failed to store the captcha answer.")]
+    RedisFailed(#[from] RedisError),
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptchaOptions {
+    /// Number of characters in the answer.
+    pub length: usize,
+    /// Characters the answer is drawn from.
+    pub charset: String,
+    /// Amount of speckles and lines drawn over the text, from `0.0` to `1.0`.
+    pub noise: f32,
+    /// Amplitude in pixels of the sine warp applied to the text.
+    pub warp: f32,
+    /// Seconds the answer is kept in Redis.
+    pub ttl: usize,
+}
+
+impl Default for CaptchaOptions {
+    fn default() -> Self {
+        Self {
+            length: 6,
+            charset: DEFAULT_CHARSET.to_string(),
+            noise: 0.5,
+            warp: 4.0,
+            ttl: 300,
+        }
+    }
+}
+
+pub struct Captcha {
+    pub answer: String,
+    pub png: Vec<u8>,
+}
+
+impl Captcha {
+    /// Generates a new image captcha with a random answer.
+    pub fn generate(options: &CaptchaOptions) -> Result<Self, CaptchaError> {
+        let answer = random_answer(options)?;
+        let png = visual::render(&answer, options)?;
+
+        Ok(Self { answer, png })
+    }
+
+    /// The image as an attachment that can be added to an interaction response.
+    pub fn attachment(&self, id: u64) -> Attachment {
+        Attachment::from_bytes(IMAGE_FILENAME.to_string(), self.png.clone(), id)
+    }
+}
+
+fn random_answer(options: &CaptchaOptions) -> Result<String, CaptchaError> {
+    let charset: Vec<char> = options.charset.chars().collect();
+    if charset.is_empty() {
+        return Err(CaptchaError::EmptyCharset);
+    }
+
+    let mut rng = rand::thread_rng();
+    Ok((0..options.length)
+        .map(|_| *charset.choose(&mut rng).unwrap())
+        .collect())
+}
+
+fn answer_key(guild_id: Id<GuildMarker>, user_id: Id<UserMarker>) -> String {
+    format!("captcha:{}:{}", guild_id.get(), user_id.get())
+}
+
+fn clip_key(guild_id: Id<GuildMarker>, user_id: Id<UserMarker>) -> String {
+    format!("captcha:{}:{}:audio", guild_id.get(), user_id.get())
+}
+
+/// Stores the expected answer for a member, replacing any pending one.
+pub async fn store_answer(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    answer: &str,
+    ttl: usize,
+) -> Result<(), CaptchaError> {
+    conn.set_ex::<_, _, ()>(answer_key(guild_id, user_id), answer, ttl)
+        .await?;
+    Ok(())
+}
+
+/// Stores an audio challenge and its answer so the clip can be sent again
+/// without changing the answer.
+pub async fn store_audio(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    captcha: &AudioCaptcha,
+    ttl: usize,
+) -> Result<(), CaptchaError> {
+    store_answer(conn, guild_id, user_id, &captcha.answer, ttl).await?;
+    conn.set_ex::<_, _, ()>(clip_key(guild_id, user_id), captcha.wav.as_slice(), ttl)
+        .await?;
+    Ok(())
+}
+
+/// Fetches the pending audio clip for a member, if there is one.
+pub async fn stored_audio(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<Option<Vec<u8>>, CaptchaError> {
+    Ok(conn.get(clip_key(guild_id, user_id)).await?)
+}
+
+/// Compares `attempt` against the stored answer, ignoring case and
+/// whitespace. The answer is consumed either way, so every guess needs a new
+/// challenge.
+pub async fn check_answer(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    attempt: &str,
+) -> Result<bool, CaptchaError> {
+    let key = answer_key(guild_id, user_id);
+    let expected: Option<String> = conn.get(&key).await?;
+    if expected.is_some() {
+        conn.del::<_, ()>(&[key, clip_key(guild_id, user_id)]).await?;
+    }
+
+    let attempt: String = attempt.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(matches!(expected, Some(expected) if expected.eq_ignore_ascii_case(&attempt)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(length: usize, charset: &str) -> CaptchaOptions {
+        CaptchaOptions {
+            length,
+            charset: charset.to_string(),
+            ..CaptchaOptions::default()
+        }
+    }
+
+    #[test]
+    fn answer_has_length_and_uses_charset() {
+        let answer = random_answer(&options(8, "AB3")).unwrap();
+
+        assert_eq!(answer.chars().count(), 8);
+        assert!(answer.chars().all(|c| "AB3".contains(c)));
+    }
+
+    #[test]
+    fn empty_charset_is_rejected() {
+        assert!(matches!(
+            random_answer(&options(6, "")),
+            Err(CaptchaError::EmptyCharset)
+        ));
+    }
+
+    #[test]
+    fn undrawable_charset_is_rejected() {
+        assert!(matches!(
+            Captcha::generate(&options(4, "#")),
+            Err(CaptchaError::UnsupportedCharacter('#'))
+        ));
+    }
+}
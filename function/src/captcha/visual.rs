@@ -0,0 +1,162 @@
+use std::{f32::consts::PI, io::Cursor};
+
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use rand::Rng;
+
+use super::{font, CaptchaError, CaptchaOptions};
+
+/// Size of a single font pixel on the canvas before jitter is applied.
+const CELL: f32 = 7.0;
+const PADDING: u32 = 24;
+
+/// Renders `answer` to a distorted PNG.
+pub fn render(answer: &str, options: &CaptchaOptions) -> Result<Vec<u8>, CaptchaError> {
+    let mut rng = rand::thread_rng();
+
+    let advance = (font::WIDTH as f32 + 2.0) * CELL;
+    let width = PADDING * 2 + (advance * answer.chars().count() as f32) as u32;
+    let height = PADDING * 2 + (font::HEIGHT as f32 * CELL) as u32;
+
+    let background = Rgb([
+        rng.gen_range(215..=255),
+        rng.gen_range(215..=255),
+        rng.gen_range(215..=255),
+    ]);
+    let mut canvas = RgbImage::from_pixel(width, height, background);
+
+    for (i, c) in answer.chars().enumerate() {
+        let glyph = font::glyph(c).ok_or(CaptchaError::UnsupportedCharacter(c))?;
+        let colour = Rgb([
+            rng.gen_range(0..=110),
+            rng.gen_range(0..=110),
+            rng.gen_range(0..=110),
+        ]);
+        let scale = CELL * rng.gen_range(0.85..=1.15);
+        let angle: f32 = rng.gen_range(-0.35..=0.35);
+        let centre_x = PADDING as f32 + advance * (i as f32 + 0.5);
+        let centre_y = height as f32 / 2.0 + rng.gen_range(-6.0..=6.0);
+
+        draw_glyph(&mut canvas, &glyph, colour, (centre_x, centre_y), scale, angle);
+    }
+
+    let mut canvas = warp(&canvas, background, options.warp, &mut rng);
+    add_noise(&mut canvas, options.noise, &mut rng);
+
+    let mut png = Vec::new();
+    DynamicImage::ImageRgb8(canvas).write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+/// Rasterises a glyph rotated by `angle` radians around `centre`.
+fn draw_glyph(
+    canvas: &mut RgbImage,
+    glyph: &[u8; 7],
+    colour: Rgb<u8>,
+    centre: (f32, f32),
+    scale: f32,
+    angle: f32,
+) {
+    let (sin, cos) = angle.sin_cos();
+    let half_w = font::WIDTH as f32 * scale / 2.0;
+    let half_h = font::HEIGHT as f32 * scale / 2.0;
+    let reach = (half_w * half_w + half_h * half_h).sqrt().ceil() as i32;
+
+    for dy in -reach..=reach {
+        for dx in -reach..=reach {
+            let (x, y) = (centre.0 as i32 + dx, centre.1 as i32 + dy);
+            if x < 0 || y < 0 || x >= canvas.width() as i32 || y >= canvas.height() as i32 {
+                continue;
+            }
+            // Map the canvas pixel back into unrotated glyph space.
+            let gx = (dx as f32 * cos + dy as f32 * sin + half_w) / scale;
+            let gy = (-(dx as f32) * sin + dy as f32 * cos + half_h) / scale;
+            if font::is_set(glyph, gx.floor() as i32, gy.floor() as i32) {
+                canvas.put_pixel(x as u32, y as u32, colour);
+            }
+        }
+    }
+}
+
+/// Displaces every pixel along two sine waves so the glyphs can't be matched
+/// against the bitmap font directly.
+fn warp(source: &RgbImage, background: Rgb<u8>, amplitude: f32, rng: &mut impl Rng) -> RgbImage {
+    if amplitude <= 0.0 {
+        return source.clone();
+    }
+
+    let (width, height) = source.dimensions();
+    let period_x = rng.gen_range(0.6..=1.2) * height as f32;
+    let period_y = rng.gen_range(0.3..=0.6) * width as f32;
+    let phase_x = rng.gen_range(0.0..2.0 * PI);
+    let phase_y = rng.gen_range(0.0..2.0 * PI);
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let sx = x as f32 + amplitude * (2.0 * PI * y as f32 / period_x + phase_x).sin();
+        let sy = y as f32 + amplitude * (2.0 * PI * x as f32 / period_y + phase_y).sin();
+        if sx < 0.0 || sy < 0.0 || sx >= width as f32 || sy >= height as f32 {
+            background
+        } else {
+            *source.get_pixel(sx as u32, sy as u32)
+        }
+    })
+}
+
+/// Scatters speckles and strike-through lines over the image. `level` is
+/// expected to be between `0.0` and `1.0`.
+fn add_noise(canvas: &mut RgbImage, level: f32, rng: &mut impl Rng) {
+    let level = level.clamp(0.0, 1.0);
+    let (width, height) = canvas.dimensions();
+
+    let speckles = (width * height) as f32 * 0.08 * level;
+    for _ in 0..speckles as u32 {
+        let shade = rng.gen_range(0..=255);
+        canvas.put_pixel(
+            rng.gen_range(0..width),
+            rng.gen_range(0..height),
+            Rgb([shade, shade, shade]),
+        );
+    }
+
+    let lines = (8.0 * level).round() as u32;
+    for _ in 0..lines {
+        let colour = Rgb([
+            rng.gen_range(0..=160),
+            rng.gen_range(0..=160),
+            rng.gen_range(0..=160),
+        ]);
+        let (x0, y0) = (0.0, rng.gen_range(0.0..height as f32));
+        let (x1, y1) = (width as f32, rng.gen_range(0.0..height as f32));
+        let steps = width;
+        for step in 0..steps {
+            let t = step as f32 / steps as f32;
+            let x = x0 + (x1 - x0) * t;
+            let y = y0 + (y1 - y0) * t;
+            for thickness in 0..2 {
+                let py = y as i32 + thickness;
+                if py >= 0 && (py as u32) < height && (x as u32) < width {
+                    canvas.put_pixel(x as u32, py as u32, colour);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::ImageFormat;
+
+    use super::*;
+
+    #[test]
+    fn renders_png_sized_to_answer() {
+        let png = render("AB23", &CaptchaOptions::default()).unwrap();
+        let image = image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap();
+
+        let advance = (font::WIDTH as f32 + 2.0) * CELL;
+        assert_eq!(image.width(), PADDING * 2 + (advance * 4.0) as u32);
+        assert_eq!(
+            image.height(),
+            PADDING * 2 + (font::HEIGHT as f32 * CELL) as u32
+        );
+    }
+}
@@ -1,6 +1,9 @@
-use deadpool_redis::redis::AsyncCommands;
+use crate::{
+    audit::{self, AuditLog},
+    config::{ConfigField, GuildConfig, LogCategory},
+};
 use twilight_model::id::Id;
-use twilight_util::builder::embed::EmbedBuilder;
+use twilight_util::builder::embed::{EmbedBuilder, EmbedFooterBuilder};
 use twilight_util::builder::InteractionResponseDataBuilder;
 use zephyrus::{
     prelude::*,
@@ -8,37 +11,124 @@ use zephyrus::{
 };
 
 #[command]
-#[description = "The channel to log joins to"]
+#[description = "The default channel to log events to"]
 async fn channel(
     ctx: &SlashContext<crate::Context>,
     #[description = "To remove, set this value to nothing"] chn: Option<Id<ChannelMarker>>,
 ) -> CommandResult {
-    dbg!(&chn);
     let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(
+        ctx.http_client.inner(),
+        guild_id,
+        ctx.interaction.author_id().unwrap(),
+    );
 
-    let current: Option<u64> = conn
-        .hget(
-            format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-            "logging:channel",
-        )
-        .await?;
+    let current = GuildConfig::load(&mut conn, guild_id)
+        .await?
+        .logging_channel;
 
-    let desc = match (current, chn.map(|o| o.get())) {
+    let desc = match (current.map(|o| o.get()), chn.map(|o| o.get())) {
         (None, None) => {
             format!("There is no logging channel set, specify the channel argument to set one.")
         }
         (Some(a), Some(b)) if a == b => format!("The logging channel is already {}", a),
         (_, Some(b)) => {
-            let _: () = conn
-                .hset(
-                    format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-                    "channel",
-                    b,
-                )
+            audit
+                .patch(&mut conn, ConfigField::LoggingChannel, Some(b.to_string()))
                 .await?;
             format!("Set the logging channel to <#{}>", b)
         }
-        (Some(_), _) => String::from("Removed the logging channel"),
+        (Some(_), None) => {
+            audit
+                .patch(&mut conn, ConfigField::LoggingChannel, None)
+                .await?;
+            String::from("Removed the logging channel")
+        }
+    };
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .embeds(vec![EmbedBuilder::new().description(desc).build()])
+                .build(),
+        ),
+    })
+}
+
+#[command]
+#[description = "Log a category of events, optionally to its own channel"]
+async fn enable(
+    ctx: &SlashContext<crate::Context>,
+    #[description = "The events to log"] category: LogCategory,
+    #[description = "Where to log them, leave empty to use the logging channel"] chn: Option<
+        Id<ChannelMarker>,
+    >,
+) -> CommandResult {
+    let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(
+        ctx.http_client.inner(),
+        guild_id,
+        ctx.interaction.author_id().unwrap(),
+    );
+
+    let mut config = GuildConfig::load(&mut conn, guild_id).await?;
+    config.disabled_logs.remove(&category);
+    match chn {
+        Some(chn) => config.log_channels.insert(category, chn),
+        None => config.log_channels.remove(&category),
+    };
+
+    for field in [ConfigField::DisabledLogs, ConfigField::LogChannel(category)] {
+        audit.patch(&mut conn, field, config.get(field)).await?;
+    }
+
+    let desc = match config.log_channel(category) {
+        Some(channel) => format!("{} will be logged to <#{}>", category.name(), channel),
+        None => format!(
+            "{} will be logged once a logging channel is set with `/logging channel`",
+            category.name()
+        ),
+    };
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .embeds(vec![EmbedBuilder::new().description(desc).build()])
+                .build(),
+        ),
+    })
+}
+
+#[command]
+#[description = "Stop logging a category of events"]
+async fn disable(
+    ctx: &SlashContext<crate::Context>,
+    #[description = "The events to stop logging"] category: LogCategory,
+) -> CommandResult {
+    let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(
+        ctx.http_client.inner(),
+        guild_id,
+        ctx.interaction.author_id().unwrap(),
+    );
+
+    let mut config = GuildConfig::load(&mut conn, guild_id).await?;
+    let desc = if config.disabled_logs.insert(category) {
+        audit
+            .patch(
+                &mut conn,
+                ConfigField::DisabledLogs,
+                config.get(ConfigField::DisabledLogs),
+            )
+            .await?;
+        format!("{} will no longer be logged", category.name())
+    } else {
+        format!("{} logging is already turned off", category.name())
     };
 
     Ok(InteractionResponse {
@@ -50,3 +140,57 @@ async fn channel(
         ),
     })
 }
+
+#[command]
+#[description = "Show recent configuration changes"]
+async fn history(
+    ctx: &SlashContext<crate::Context>,
+    #[description = "The page to show, starting at 1"] page: Option<i64>,
+    #[description = "Only show changes to this setting"] field: Option<String>,
+) -> CommandResult {
+    let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+
+    if let Some(field) = field.as_deref() {
+        if ConfigField::from_key(field).is_none() {
+            return Err(
+                format!("`{}` is not a setting, pick one of the suggestions.", field).into(),
+            );
+        }
+    }
+
+    // The log is capped, so pages past what it can hold are always empty.
+    let last_page = (audit::MAX_ENTRIES + audit::PAGE_SIZE - 1) / audit::PAGE_SIZE;
+    let page = page.unwrap_or(1).clamp(1, last_page as i64) as usize;
+    let (entries, total) = audit::history(&mut conn, guild_id, field.as_deref(), page - 1).await?;
+    let pages = ((total + audit::PAGE_SIZE - 1) / audit::PAGE_SIZE).max(1);
+
+    let desc = if entries.is_empty() && total == 0 {
+        String::from("No configuration changes have been recorded yet.")
+    } else if entries.is_empty() {
+        format!("There are only {} pages of changes.", pages)
+    } else {
+        entries
+            .iter()
+            .map(|entry| entry.summary())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .embeds(vec![EmbedBuilder::new()
+                    .title("Configuration history")
+                    .description(desc)
+                    .footer(EmbedFooterBuilder::new(format!(
+                        "Page {} of {}",
+                        page.min(pages),
+                        pages
+                    )))
+                    .build()])
+                .build(),
+        ),
+    })
+}
@@ -1,74 +1,198 @@
-use deadpool_redis::redis::AsyncCommands;
-use twilight_model::{id::Id, guild::{Role}};
-use twilight_util::builder::{InteractionResponseDataBuilder, embed::{EmbedBuilder, EmbedFooterBuilder}};
+use twilight_model::{
+    application::component::{button::ButtonStyle, ActionRow, Button, Component},
+    guild::{Permissions, Role},
+    id::{
+        marker::{GuildMarker, UserMarker},
+        Id,
+    },
+};
+use twilight_util::builder::{InteractionResponseDataBuilder, embed::{EmbedBuilder, EmbedFieldBuilder, EmbedFooterBuilder}};
+use crate::{
+    audit::AuditLog,
+    cache::{self, CacheError},
+    config::{ConfigField, GuildConfig, TimeoutAction, VerificationType},
+    timeouts,
+};
 use zephyrus::{
     prelude::*,
-    twilight_exports::{InteractionResponse, InteractionResponseType, RoleMarker},
+    twilight_exports::{ChannelMarker, InteractionResponse, InteractionResponseType, RoleMarker},
 };
 
+#[command]
+#[description = "Post the verification gate message"]
+async fn setup(
+    ctx: &SlashContext<crate::Context>,
+    #[description = "The channel new members will verify in"] channel: Id<ChannelMarker>,
+) -> CommandResult {
+    let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(ctx.http_client.inner(), guild_id, ctx.interaction.author_id().unwrap());
+
+    let gate = EmbedBuilder::new()
+        .title("Verification")
+        .description("This server requires you to verify before you can chat.\nPress the button below to get started.")
+        .build();
+    let button = Component::ActionRow(ActionRow {
+        components: vec![Component::Button(Button {
+            custom_id: Some("verify:start".to_string()),
+            disabled: false,
+            emoji: None,
+            label: Some("Verify".to_string()),
+            style: ButtonStyle::Success,
+            url: None,
+        })],
+    });
+
+    let message = ctx
+        .http_client
+        .inner()
+        .create_message(channel)
+        .embeds(&[gate])?
+        .components(&[button])?
+        .exec()
+        .await?
+        .model()
+        .await?;
+
+    let mut config = GuildConfig::load(&mut conn, guild_id).await?;
+    if let (Some(old_channel), Some(old_message)) = (config.gate_channel, config.gate_message) {
+        // The old gate may already be gone, it only needs to not linger.
+        let _ = ctx
+            .http_client
+            .inner()
+            .delete_message(old_channel, old_message)
+            .exec()
+            .await;
+    }
+
+    config.gate_channel = Some(channel);
+    config.gate_message = Some(message.id);
+    for field in [ConfigField::GateChannel, ConfigField::GateMessage] {
+        audit.patch(&mut conn, field, config.get(field)).await?;
+    }
+
+    let mut embed = EmbedBuilder::new().description(format!(
+        "Posted the verification gate in <#{}>",
+        channel.get()
+    ));
+    if config.verification_role.is_none() {
+        embed = embed.footer(EmbedFooterBuilder::new(":warning: No verification role set. Set one with /verification role."));
+    }
+
+    Ok(
+        InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseDataBuilder::new().embeds(vec![
+                embed.build()
+            ]).build()),
+        }
+    )
+}
+
+/// Why a role can't be used as the verification role, if there is a reason.
+pub(crate) async fn role_problem(
+    conn: &mut deadpool_redis::Connection,
+    guild_id: Id<GuildMarker>,
+    bot_id: Id<UserMarker>,
+    role: &Role,
+) -> Result<Option<String>, CacheError> {
+    if role.managed {
+        return Ok(Some(format!(
+            "<@&{}> is managed by an integration and can't be given out by me.",
+            role.id
+        )));
+    }
+
+    let dangerous = role.permissions
+        & (Permissions::ADMINISTRATOR | Permissions::MANAGE_GUILD | Permissions::BAN_MEMBERS);
+    if !dangerous.is_empty() {
+        let names: Vec<&str> = [
+            (Permissions::ADMINISTRATOR, "Administrator"),
+            (Permissions::MANAGE_GUILD, "Manage Server"),
+            (Permissions::BAN_MEMBERS, "Ban Members"),
+        ]
+        .iter()
+        .filter(|(permission, _)| dangerous.contains(*permission))
+        .map(|(_, name)| *name)
+        .collect();
+        return Ok(Some(format!(
+            "<@&{}> has the {} permission, anyone passing the captcha would get it too.",
+            role.id,
+            names.join(", ")
+        )));
+    }
+
+    match cache::highest_position(conn, guild_id, bot_id).await? {
+        Some(highest) if role.position >= highest => Ok(Some(format!(
+            "<@&{}> is not below my highest role, move my role above it first.",
+            role.id
+        ))),
+        _ => Ok(None),
+    }
+}
+
 #[command]
 #[description = "Set the verification role"]
 async fn role(
     ctx: &SlashContext<crate::Context>,
     #[description = "The role to assign users once they have completed verification"] role: Option<Id<RoleMarker>>,
 ) -> CommandResult {
-    dbg!(&role);
     let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(ctx.http_client.inner(), guild_id, ctx.interaction.author_id().unwrap());
 
+    let cached = match role {
+        Some(role) => cache::role(&mut conn, guild_id, role).await?,
+        None => None,
+    };
+    // `@everyone` shares its id with the guild.
+    let default_role = role.map_or(false, |role| role.get() == guild_id.get());
 
-    let default_role = if let Some(role) = &role {
-        let data: String = conn.get(format!("role:{}:{}", ctx.interaction.guild_id.unwrap().get(), role.get())).await?;
-        let rle : Role = serde_json::from_str(&data).unwrap();
-        rle.name == "@everyone"
-    } else {
-        false
+    let problem = match (&role, &cached) {
+        (Some(_), _) if default_role => None,
+        (Some(_), None) => Some(String::from("I couldn't find that role yet, try again in a moment.")),
+        (_, Some(cached)) => role_problem(&mut conn, guild_id, ctx.data.bot_id, cached).await?,
+        (None, None) => None,
     };
-    
-    let current: Option<u64> = conn
-        .hget(
-            format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-            "verification:role",
-        )
-        .await?;
-    
-    let desc = match (current, role.map(|o|o.get())) {
+
+    if let Some(problem) = problem {
+        return Ok(
+            InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(InteractionResponseDataBuilder::new().embeds(vec![
+                    EmbedBuilder::new().description(problem).build()
+                ]).build()),
+            }
+        );
+    }
+
+    let config = GuildConfig::load(&mut conn, guild_id).await?;
+
+    let desc = match (config.verification_role.map(|o| o.get()), role.map(|o|o.get())) {
         (None, None) => {
             format!("The verification role will be given to users once they have completed verification.\nYou can set any role that is currently below the bot's top role.\n\nYou can set the type of verification with `/verification type`.\nTo setup the verification gate run `/verification setup`.")
         }
         (Some(a), Some(b)) if a == b => format!("The verification role is already <@&{}>", a),
         (None, Some(_)) if default_role => format!("There is no verification role set, specify the role argument to set one."),
         (_, Some(_)) if default_role => {
-            let _: () = conn
-                .hdel(
-                    format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-                    "verification:role"
-                )
-                .await?;
+            audit.patch(&mut conn, ConfigField::VerificationRole, None).await?;
             String::from("Removed the verification role")
         }
         (_, Some(b)) => {
-            let _: () = conn
-                .hset(
-                    format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-                    "verification:role",
-                    b,
-                )
-                .await?;
+            audit.patch(&mut conn, ConfigField::VerificationRole, Some(b.to_string())).await?;
             format!("Set the verification role to <@&{}>", b)
         }
-        (Some(_), None) => String::from("Removed the verification role"),
+        (Some(_), None) => {
+            audit.patch(&mut conn, ConfigField::VerificationRole, None).await?;
+            String::from("Removed the verification role")
+        }
     };
 
     let mut embed = EmbedBuilder::new().description(desc);
 
-    if !conn
-        .hexists::<_, _, bool>(
-            format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-            "verification:message",
-        )
-        .await? {
-            embed = embed.footer(EmbedFooterBuilder::new(":warning: No verification message set. Without one, users will not recieve this role. Set one up with /verification setup."))
-        }
+    if config.gate_message.is_none() {
+        embed = embed.footer(EmbedFooterBuilder::new(":warning: No verification message set. Without one, users will not recieve this role. Set one up with /verification setup."))
+    }
 
     Ok(
         InteractionResponse {
@@ -80,62 +204,62 @@ async fn role(
     )
 }
 
-#[derive(Parse, Debug, Clone, Eq, PartialEq)]
-enum VerificationType {
-    None,
-    Captcha,
-    Audio,
-}
+#[command]
+#[description = "Set a role that is removed from users once they have completed verification"]
+async fn unverified(
+    ctx: &SlashContext<crate::Context>,
+    #[description = "To remove, set this value to nothing"] role: Option<Id<RoleMarker>>,
+) -> CommandResult {
+    let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(ctx.http_client.inner(), guild_id, ctx.interaction.author_id().unwrap());
+
+    let current = GuildConfig::load(&mut conn, guild_id).await?.unverified_role;
 
-impl From<String> for VerificationType {
-    fn from(s: String) -> Self {
-        match s.as_str() {
-            "None" => VerificationType::None,
-            "Captcha" => VerificationType::Captcha,
-            "Audio" => VerificationType::Audio,
-            _ => VerificationType::None,
+    let desc = match (current.map(|o| o.get()), role.map(|o| o.get())) {
+        (None, None) => String::from("There is no unverified role set, specify the role argument to set one."),
+        (Some(a), Some(b)) if a == b => format!("The unverified role is already <@&{}>", a),
+        (_, Some(b)) => {
+            audit.patch(&mut conn, ConfigField::UnverifiedRole, Some(b.to_string())).await?;
+            format!("Set the unverified role to <@&{}>", b)
         }
-    }
-}
+        (Some(_), None) => {
+            audit.patch(&mut conn, ConfigField::UnverifiedRole, None).await?;
+            String::from("Removed the unverified role")
+        }
+    };
 
-impl ToString for VerificationType {
-    fn to_string(&self) -> String {
-        match self {
-            VerificationType::None => String::from("None"),
-            VerificationType::Captcha => String::from("Captcha"),
-            VerificationType::Audio => String::from("Audio"),
+    Ok(
+        InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseDataBuilder::new().embeds(vec![
+                EmbedBuilder::new().description(desc).build(),
+            ]).build()),
         }
-    }
+    )
 }
 
 #[command("type")]
 #[description = "Set the type of verification to use when a user joins the server"]
 async fn typ(
     ctx: &SlashContext<crate::Context>,
-    #[description = "the type of join gate"] choice: VerificationType,
+    #[description = "the type of join gate"] choice: String,
 ) -> CommandResult {
-    
+    let choice = VerificationType::ALL
+        .into_iter()
+        .find(|kind| kind.to_string().eq_ignore_ascii_case(choice.trim()))
+        .ok_or_else(|| format!("`{}` is not a verification type, pick one of the suggestions.", choice))?;
+
     let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(ctx.http_client.inner(), guild_id, ctx.interaction.author_id().unwrap());
+
+    let current = GuildConfig::load(&mut conn, guild_id).await?.verification_type;
 
-    let current : VerificationType = conn
-        .hget::<_, _, Option<String>>(
-            format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-            "verification:type",
-        )
-        .await?
-        .map(|o| VerificationType::from(o))
-        .unwrap_or(VerificationType::None);
-    
     let desc = match (current, choice) {
         (a, b) if a == b => format!("The verification type is already `{}`", a.to_string()),
         (_, b) => {
-            let _: () = conn
-                .hset(
-                    format!("config:{}", ctx.interaction.guild_id.unwrap().get()),
-                    "type",
-                    b.to_string(),
-                )
-                .await?;
+            audit.patch(&mut conn, ConfigField::VerificationType, Some(b.to_string())).await?;
             format!("Set the verification type to `{}`", b.to_string())
         }
     };
@@ -148,3 +272,169 @@ async fn typ(
         }
     )
 }
+
+#[command]
+#[description = "Kick or quarantine members who don't verify in time"]
+async fn timeout(
+    ctx: &SlashContext<crate::Context>,
+    #[description = "Minutes new members have to verify in, run without any options to turn the timeout off"] minutes: Option<i64>,
+    #[description = "What to do once the time is up, kicks by default"] action: Option<TimeoutAction>,
+    #[description = "The role to give when quarantining"] quarantine: Option<Id<RoleMarker>>,
+) -> CommandResult {
+    let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let audit = AuditLog::new(ctx.http_client.inner(), guild_id, ctx.interaction.author_id().unwrap());
+
+    let mut config = GuildConfig::load(&mut conn, guild_id).await?;
+
+    if let Some(quarantine) = quarantine {
+        let highest = cache::highest_position(&mut conn, guild_id, ctx.data.bot_id).await?;
+        match cache::role(&mut conn, guild_id, quarantine).await? {
+            Some(role) if role.managed || highest.map_or(false, |highest| role.position >= highest) => {
+                return Err(format!("I can't give out <@&{}>, pick a role below my highest role.", quarantine).into());
+            }
+            _ => {}
+        }
+    }
+
+    config.timeout = match minutes {
+        Some(minutes) if (1..=timeouts::MAX_MINUTES as i64).contains(&minutes) => Some(minutes as u64),
+        Some(_) => return Err(format!("The timeout has to be between 1 and {} minutes.", timeouts::MAX_MINUTES).into()),
+        None if action.is_none() && quarantine.is_none() => None,
+        None => config.timeout,
+    };
+    if let Some(action) = action {
+        config.timeout_action = action;
+    }
+    if quarantine.is_some() {
+        config.quarantine_role = quarantine;
+    }
+
+    for field in [ConfigField::Timeout, ConfigField::TimeoutAction, ConfigField::QuarantineRole] {
+        audit.patch(&mut conn, field, config.get(field)).await?;
+    }
+
+    let mut embed = EmbedBuilder::new().description(match (config.timeout, config.timeout_action) {
+        (None, _) => String::from("Members can take as long as they like to verify."),
+        (Some(minutes), TimeoutAction::Kick) => format!("Members who don't verify within {} minutes will be kicked.", minutes),
+        (Some(minutes), TimeoutAction::Quarantine) => format!("Members who don't verify within {} minutes will be quarantined.", minutes),
+    });
+    if config.timeout.is_some() && config.timeout_action == TimeoutAction::Quarantine && config.quarantine_role.is_none() {
+        embed = embed.footer(EmbedFooterBuilder::new(":warning: No quarantine role set, members won't be quarantined until one is set with the quarantine argument."));
+    }
+
+    Ok(
+        InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseDataBuilder::new().embeds(vec![
+                embed.build()
+            ]).build()),
+        }
+    )
+}
+
+#[command]
+#[description = "Show the current verification settings and anything that needs fixing"]
+async fn status(ctx: &SlashContext<crate::Context>) -> CommandResult {
+    let mut conn = ctx.data.redis.get().await?;
+    let guild_id = ctx.interaction.guild_id.unwrap();
+    let bot_id = ctx.data.bot_id;
+
+    let config = GuildConfig::load(&mut conn, guild_id).await?;
+    let mut warnings = Vec::new();
+
+    let role = match config.verification_role {
+        Some(role) => {
+            match cache::role(&mut conn, guild_id, role).await? {
+                Some(cached) => {
+                    if let Some(problem) = role_problem(&mut conn, guild_id, bot_id, &cached).await? {
+                        warnings.push(problem);
+                    }
+                }
+                None => warnings.push(format!("<@&{}> no longer exists, set a new one with `/verification role`.", role)),
+            }
+            format!("<@&{}>", role)
+        }
+        None => {
+            warnings.push(String::from("No verification role set, set one with `/verification role`."));
+            String::from("Not set")
+        }
+    };
+
+    let gate = match (config.gate_channel, config.gate_message) {
+        (Some(channel), Some(message)) => format!(
+            "[Message](https://discord.com/channels/{}/{}/{}) in <#{}>",
+            guild_id, channel, message, channel
+        ),
+        _ => {
+            warnings.push(String::from("No verification message set, post one with `/verification setup`."));
+            String::from("Not set")
+        }
+    };
+
+    match cache::permissions(&mut conn, guild_id, bot_id).await? {
+        Some(permissions) if !permissions.contains(Permissions::ADMINISTRATOR) => {
+            for (permission, name) in [
+                (Permissions::MANAGE_ROLES, "Manage Roles"),
+                (Permissions::VIEW_CHANNEL, "View Channels"),
+                (Permissions::SEND_MESSAGES, "Send Messages"),
+                (Permissions::KICK_MEMBERS, "Kick Members"),
+            ] {
+                if permission == Permissions::KICK_MEMBERS && (config.timeout.is_none() || config.timeout_action != TimeoutAction::Kick) {
+                    continue;
+                }
+                if !permissions.contains(permission) {
+                    warnings.push(format!("I'm missing the {} permission.", name));
+                }
+            }
+        }
+        Some(_) => {}
+        None => warnings.push(String::from("I couldn't find my own roles, permissions were not checked.")),
+    }
+
+    if config.verification_type == VerificationType::None {
+        warnings.push(String::from("Verification is turned off, pick a type with `/verification type`."));
+    }
+
+    let timeout = match (config.timeout, config.timeout_action) {
+        (None, _) => String::from("Off"),
+        (Some(minutes), TimeoutAction::Kick) => format!("Kick after {} minutes", minutes),
+        (Some(minutes), TimeoutAction::Quarantine) => {
+            if config.quarantine_role.is_none() {
+                warnings.push(String::from("The timeout quarantines members but no quarantine role is set, set one with `/verification timeout`."));
+            }
+            format!("Quarantine after {} minutes", minutes)
+        }
+    };
+
+    let mut embed = EmbedBuilder::new()
+        .title("Verification status")
+        .field(EmbedFieldBuilder::new("Type", format!("`{}`", config.verification_type.to_string())).inline())
+        .field(EmbedFieldBuilder::new("Role", role).inline())
+        .field(EmbedFieldBuilder::new(
+            "Unverified role",
+            config.unverified_role.map_or(String::from("Not set"), |role| format!("<@&{}>", role)),
+        ).inline())
+        .field(EmbedFieldBuilder::new("Gate", gate).inline())
+        .field(EmbedFieldBuilder::new("Timeout", timeout).inline())
+        .field(EmbedFieldBuilder::new(
+            "Logging channel",
+            config.logging_channel.map_or(String::from("Not set"), |channel| format!("<#{}>", channel)),
+        ).inline());
+
+    if !warnings.is_empty() {
+        embed = embed.field(EmbedFieldBuilder::new(
+            ":warning: Needs attention",
+            warnings.iter().map(|w| format!("• {}", w)).collect::<Vec<_>>().join("\n"),
+        ));
+    }
+
+    Ok(
+        InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseDataBuilder::new().embeds(vec![
+                embed.build()
+            ]).build()),
+        }
+    )
+}
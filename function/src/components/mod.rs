@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use futures::future::BoxFuture;
+use twilight_http::{client::InteractionClient, Client};
+use twilight_model::{
+    application::interaction::{MessageComponentInteraction, ModalSubmitInteraction},
+    channel::message::MessageFlags,
+    http::interaction::{InteractionResponse, InteractionResponseType},
+    id::{marker::ApplicationMarker, Id},
+};
+use twilight_util::builder::InteractionResponseDataBuilder;
+
+use crate::{autocomplete::AutocompleteRouter, deferred::Defer, Context};
+
+pub mod verification;
+
+pub type ComponentResult =
+    Result<InteractionResponse, Box<dyn std::error::Error + Send + Sync>>;
+
+/// A handler for interactions whose `custom_id` starts with a registered prefix.
+pub type ComponentFn<I> =
+    for<'a> fn(&'a ComponentContext<'a, I>) -> BoxFuture<'a, ComponentResult>;
+
+/// Interactions that carry a developer defined `custom_id`.
+pub trait CustomId {
+    fn custom_id(&self) -> &str;
+}
+
+impl CustomId for MessageComponentInteraction {
+    fn custom_id(&self) -> &str {
+        &self.data.custom_id
+    }
+}
+
+impl CustomId for ModalSubmitInteraction {
+    fn custom_id(&self) -> &str {
+        &self.data.custom_id
+    }
+}
+
+/// Routers for every interaction kind that isn't a slash command, plus the
+/// slash commands that are answered with a deferred response.
+pub struct Components {
+    pub buttons: ComponentRouter<MessageComponentInteraction>,
+    pub modals: ComponentRouter<ModalSubmitInteraction>,
+    pub autocomplete: AutocompleteRouter,
+    /// Keyed by command path, e.g. `"verification setup"`.
+    pub deferred_commands: HashMap<&'static str, Defer>,
+}
+
+pub struct ComponentContext<'a, I> {
+    pub http_client: &'a Client,
+    pub interaction_client: InteractionClient<'a>,
+    pub data: &'a Context,
+    pub interaction: I,
+    pub application_id: Id<ApplicationMarker>,
+    /// Whatever followed the matched prefix in the `custom_id`, without the
+    /// leading `:`.
+    pub args: String,
+}
+
+/// Routes interactions to handlers by the prefix of their `custom_id`.
+/// Prefixes are matched on `:` boundaries and the longest match wins, so
+/// `verify:start` handles both `verify:start` and `verify:start:123`.
+pub struct ComponentRouter<I> {
+    routes: Vec<(&'static str, ComponentFn<I>, Option<Defer>)>,
+}
+
+impl<I: CustomId> ComponentRouter<I> {
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Registers a handler for a `custom_id` prefix.
+    pub fn route(mut self, prefix: &'static str, fun: ComponentFn<I>) -> Self {
+        self.routes.push((prefix, fun, None));
+        self
+    }
+
+    /// Registers a handler that is acknowledged with `defer` before it runs,
+    /// for anything that may take longer than Discord's deadline. It can't
+    /// respond with a modal.
+    pub fn route_deferred(
+        mut self,
+        prefix: &'static str,
+        defer: Defer,
+        fun: ComponentFn<I>,
+    ) -> Self {
+        self.routes.push((prefix, fun, Some(defer)));
+        self
+    }
+
+    fn find(&self, custom_id: &str) -> Option<(ComponentFn<I>, Option<Defer>, String)> {
+        self.routes
+            .iter()
+            .filter_map(|(prefix, fun, defer)| {
+                let rest = custom_id.strip_prefix(prefix)?;
+                let args = if rest.is_empty() {
+                    String::new()
+                } else {
+                    rest.strip_prefix(':')?.to_string()
+                };
+                Some((prefix.len(), *fun, *defer, args))
+            })
+            .max_by_key(|(len, _, _, _)| *len)
+            .map(|(_, fun, defer, args)| (fun, defer, args))
+    }
+
+    /// How the handler for `custom_id` wants to be acknowledged, `None` when
+    /// it answers directly.
+    pub fn defer_for(&self, custom_id: &str) -> Option<Defer> {
+        self.find(custom_id).and_then(|(_, defer, _)| defer)
+    }
+
+    /// Runs the handler registered for the interaction, replying with an
+    /// ephemeral message when there is none or it fails.
+    pub async fn handle(
+        &self,
+        http_client: &Client,
+        data: &Context,
+        application_id: Id<ApplicationMarker>,
+        interaction: I,
+    ) -> InteractionResponse {
+        let (fun, _, args) = match self.find(interaction.custom_id()) {
+            Some(found) => found,
+            None => return ephemeral("This component is no longer available."),
+        };
+
+        let context = ComponentContext {
+            http_client,
+            interaction_client: http_client.interaction(application_id),
+            data,
+            interaction,
+            application_id,
+            args,
+        };
+
+        match fun(&context).await {
+            Ok(response) => response,
+            Err(why) => ephemeral(format!("{}", why)),
+        }
+    }
+}
+
+impl<I: CustomId> Default for ComponentRouter<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A message only the user who triggered the interaction can see.
+pub fn ephemeral<S: Into<String>>(content: S) -> InteractionResponse {
+    InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .content(content.into())
+                .flags(MessageFlags::EPHEMERAL)
+                .build(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Button(&'static str);
+
+    impl CustomId for Button {
+        fn custom_id(&self) -> &str {
+            self.0
+        }
+    }
+
+    fn handler<'a>(_: &'a ComponentContext<'a, Button>) -> BoxFuture<'a, ComponentResult> {
+        Box::pin(async { Ok(ephemeral("")) })
+    }
+
+    fn router() -> ComponentRouter<Button> {
+        ComponentRouter::new()
+            .route("verify", handler)
+            .route_deferred("verify:start", Defer::Update, handler)
+    }
+
+    #[test]
+    fn matches_on_colon_boundaries() {
+        let router: ComponentRouter<Button> = ComponentRouter::new().route("verify:start", handler);
+
+        assert!(router.find("verify:start").is_some());
+        assert!(router.find("verify:startx").is_none());
+        assert!(router.find("verify").is_none());
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let router = router();
+
+        assert_eq!(router.defer_for("verify:start"), Some(Defer::Update));
+        assert_eq!(router.defer_for("verify:start:123"), Some(Defer::Update));
+        assert_eq!(router.defer_for("verify:answer"), None);
+    }
+
+    #[test]
+    fn extracts_args_after_prefix() {
+        let router = router();
+        let args = |custom_id| router.find(custom_id).map(|(_, _, args)| args);
+
+        assert_eq!(args("verify:start"), Some(String::new()));
+        assert_eq!(args("verify:start:123:abc"), Some(String::from("123:abc")));
+        assert_eq!(args("verify:startx"), Some(String::from("startx")));
+    }
+}
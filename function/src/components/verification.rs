@@ -0,0 +1,313 @@
+use deadpool_redis::Connection;
+use futures::future::BoxFuture;
+use twilight_model::{
+    application::{
+        component::{
+            button::ButtonStyle, text_input::TextInputStyle, ActionRow, Button, Component,
+            TextInput,
+        },
+        interaction::{MessageComponentInteraction, ModalSubmitInteraction},
+    },
+    channel::message::MessageFlags,
+    http::{
+        attachment::Attachment,
+        interaction::{InteractionResponse, InteractionResponseType},
+    },
+    id::{marker::GuildMarker, Id},
+};
+use twilight_util::builder::{
+    embed::{EmbedBuilder, ImageSource},
+    InteractionResponseDataBuilder,
+};
+
+use super::{ephemeral, ComponentContext, ComponentResult};
+use crate::{
+    captcha::{
+        self, AudioCaptcha, Captcha, CaptchaOptions, AUDIO_CHARSET, AUDIO_FILENAME, IMAGE_FILENAME,
+    },
+    config::{GuildConfig, VerificationType},
+    logger::{self, Logger, MemberEvent, Outcome},
+    roles::RoleService,
+    timeouts,
+};
+
+/// Custom id of the text input holding the member's answer.
+const ANSWER_INPUT: &str = "answer";
+
+/// Sends the member a fresh challenge. Registered for both `verify:start` and
+/// `verify:retry`.
+pub fn start<'a>(
+    ctx: &'a ComponentContext<'a, MessageComponentInteraction>,
+) -> BoxFuture<'a, ComponentResult> {
+    Box::pin(challenge(ctx))
+}
+
+async fn challenge(ctx: &ComponentContext<'_, MessageComponentInteraction>) -> ComponentResult {
+    let guild_id = ctx
+        .interaction
+        .guild_id
+        .ok_or("Verification can only be done inside a server.")?;
+    let user_id = ctx
+        .interaction
+        .author_id()
+        .ok_or("Could not work out who clicked the button.")?;
+
+    let mut conn = ctx.data.redis.get().await?;
+    let kind = GuildConfig::load(&mut conn, guild_id)
+        .await?
+        .verification_type;
+
+    let options = CaptchaOptions::default();
+    let (data, audio) = match kind {
+        VerificationType::None => {
+            return Ok(ephemeral("Verification is not enabled on this server."))
+        }
+        VerificationType::Captcha => {
+            let captcha = Captcha::generate(&options)?;
+            captcha::store_answer(&mut conn, guild_id, user_id, &captcha.answer, options.ttl)
+                .await?;
+
+            let data = InteractionResponseDataBuilder::new()
+                .embeds(vec![EmbedBuilder::new()
+                    .description("Type the characters shown in the image below.")
+                    .image(ImageSource::attachment(IMAGE_FILENAME)?)
+                    .build()])
+                .attachments(vec![captcha.attachment(0)]);
+            (data, false)
+        }
+        VerificationType::Audio => {
+            let options = CaptchaOptions {
+                charset: AUDIO_CHARSET.to_string(),
+                ..options
+            };
+            let captcha = AudioCaptcha::generate(&options)?;
+            captcha::store_audio(&mut conn, guild_id, user_id, &captcha, options.ttl).await?;
+
+            (audio_challenge(captcha.attachment(0)), true)
+        }
+    };
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(
+            data.components(vec![challenge_row(audio)])
+                .flags(MessageFlags::EPHEMERAL)
+                .build(),
+        ),
+    })
+}
+
+/// Sends the pending audio clip again without changing the answer.
+pub fn replay<'a>(
+    ctx: &'a ComponentContext<'a, MessageComponentInteraction>,
+) -> BoxFuture<'a, ComponentResult> {
+    Box::pin(replay_clip(ctx))
+}
+
+async fn replay_clip(ctx: &ComponentContext<'_, MessageComponentInteraction>) -> ComponentResult {
+    let guild_id = ctx
+        .interaction
+        .guild_id
+        .ok_or("Verification can only be done inside a server.")?;
+    let user_id = ctx
+        .interaction
+        .author_id()
+        .ok_or("Could not work out who clicked the button.")?;
+
+    let mut conn = ctx.data.redis.get().await?;
+    let data = match captcha::stored_audio(&mut conn, guild_id, user_id).await? {
+        Some(wav) => audio_challenge(Attachment::from_bytes(AUDIO_FILENAME.to_string(), wav, 0))
+            .components(vec![challenge_row(true)]),
+        None => InteractionResponseDataBuilder::new()
+            .content("The challenge expired.")
+            .components(vec![Component::ActionRow(ActionRow {
+                components: vec![retry_button()],
+            })]),
+    };
+
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::ChannelMessageWithSource,
+        data: Some(data.flags(MessageFlags::EPHEMERAL).build()),
+    })
+}
+
+fn audio_challenge(clip: Attachment) -> InteractionResponseDataBuilder {
+    InteractionResponseDataBuilder::new()
+        .embeds(vec![EmbedBuilder::new()
+            .description("Listen to the attached clip and type the digits you hear.")
+            .build()])
+        .attachments(vec![clip])
+}
+
+/// Opens the modal the member types their answer into.
+pub fn answer<'a>(
+    _ctx: &'a ComponentContext<'a, MessageComponentInteraction>,
+) -> BoxFuture<'a, ComponentResult> {
+    Box::pin(answer_modal())
+}
+
+async fn answer_modal() -> ComponentResult {
+    Ok(InteractionResponse {
+        kind: InteractionResponseType::Modal,
+        data: Some(
+            InteractionResponseDataBuilder::new()
+                .custom_id("verify:submit")
+                .title("Verification")
+                .components(vec![Component::ActionRow(ActionRow {
+                    components: vec![Component::TextInput(TextInput {
+                        custom_id: ANSWER_INPUT.to_string(),
+                        label: "Answer".to_string(),
+                        max_length: Some(32),
+                        min_length: Some(1),
+                        placeholder: Some("What did the captcha say?".to_string()),
+                        required: Some(true),
+                        style: TextInputStyle::Short,
+                        value: None,
+                    })],
+                })])
+                .build(),
+        ),
+    })
+}
+
+/// Checks the submitted answer and hands out the verification role.
+pub fn submit<'a>(
+    ctx: &'a ComponentContext<'a, ModalSubmitInteraction>,
+) -> BoxFuture<'a, ComponentResult> {
+    Box::pin(check(ctx))
+}
+
+async fn check(ctx: &ComponentContext<'_, ModalSubmitInteraction>) -> ComponentResult {
+    let guild_id = ctx
+        .interaction
+        .guild_id
+        .ok_or("Verification can only be done inside a server.")?;
+    let user_id = ctx
+        .interaction
+        .author_id()
+        .ok_or("Could not work out who submitted the answer.")?;
+
+    let attempt = ctx
+        .interaction
+        .data
+        .components
+        .iter()
+        .flat_map(|row| row.components.iter())
+        .find(|component| component.custom_id == ANSWER_INPUT)
+        .map(|component| component.value.as_str())
+        .unwrap_or_default();
+
+    let mut conn = ctx.data.redis.get().await?;
+    if !captcha::check_answer(&mut conn, guild_id, user_id, attempt).await? {
+        log_attempt(ctx, &mut conn, guild_id, false).await;
+        return Ok(InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(
+                InteractionResponseDataBuilder::new()
+                    .content("That answer was wrong or the challenge expired.")
+                    .components(vec![Component::ActionRow(ActionRow {
+                        components: vec![retry_button()],
+                    })])
+                    .flags(MessageFlags::EPHEMERAL)
+                    .build(),
+            ),
+        });
+    }
+
+    RoleService::new(ctx.http_client, ctx.data.bot_id)
+        .grant_verified(&mut conn, guild_id, user_id)
+        .await?;
+    // The deadline re-checks the role before acting, so a stale entry is harmless.
+    if let Err(why) = timeouts::cancel(&mut conn, guild_id, user_id).await {
+        tracing::warn!(error = ?why, "failed to cancel verification timeout");
+    }
+    log_attempt(ctx, &mut conn, guild_id, true).await;
+
+    Ok(ephemeral("You have been verified, welcome!"))
+}
+
+/// Records the attempt and posts it to the logging channel. Failing to log
+/// shouldn't stop anyone from verifying, so errors are only traced.
+async fn log_attempt(
+    ctx: &ComponentContext<'_, ModalSubmitInteraction>,
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    passed: bool,
+) {
+    let user = match ctx
+        .interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.user.as_ref())
+        .or(ctx.interaction.user.as_ref())
+    {
+        Some(user) => user,
+        None => return,
+    };
+
+    let (attempts, time_taken) = match logger::record_attempt(conn, guild_id, user.id).await {
+        Ok(tracked) => tracked,
+        Err(why) => {
+            tracing::warn!(error = ?why, "failed to record verification attempt");
+            return;
+        }
+    };
+    if passed {
+        if let Err(why) = logger::forget(conn, guild_id, user.id).await {
+            tracing::warn!(error = ?why, "failed to clear verification tracking");
+        }
+    }
+
+    let event = MemberEvent::Verification {
+        user,
+        outcome: if passed {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        },
+        attempts,
+        time_taken,
+    };
+    if let Err(why) = Logger::new(ctx.http_client)
+        .log(conn, guild_id, event)
+        .await
+    {
+        tracing::warn!(error = ?why, "failed to log verification attempt");
+    }
+}
+
+/// Buttons under a challenge, audio ones can also be played again.
+fn challenge_row(audio: bool) -> Component {
+    let mut components = vec![Component::Button(Button {
+        custom_id: Some("verify:answer".to_string()),
+        disabled: false,
+        emoji: None,
+        label: Some("Answer".to_string()),
+        style: ButtonStyle::Primary,
+        url: None,
+    })];
+    if audio {
+        components.push(Component::Button(Button {
+            custom_id: Some("verify:replay".to_string()),
+            disabled: false,
+            emoji: None,
+            label: Some("Play again".to_string()),
+            style: ButtonStyle::Secondary,
+            url: None,
+        }));
+    }
+    components.push(retry_button());
+
+    Component::ActionRow(ActionRow { components })
+}
+
+fn retry_button() -> Component {
+    Component::Button(Button {
+        custom_id: Some("verify:retry".to_string()),
+        disabled: false,
+        emoji: None,
+        label: Some("New challenge".to_string()),
+        style: ButtonStyle::Secondary,
+        url: None,
+    })
+}
@@ -0,0 +1,556 @@
+//! The per guild configuration stored in the `config:{guild}` Redis hash.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use deadpool_redis::{
+    redis::{AsyncCommands, RedisError},
+    Connection,
+};
+use twilight_model::id::{
+    marker::{ChannelMarker, GuildMarker, MessageMarker, RoleMarker},
+    Id,
+};
+use zephyrus::prelude::*;
+
+/// Version of the field layout below, stored in the hash under
+/// [`VERSION_FIELD`]. Bump it and add a step to [`migrate`] when renaming fields.
+pub const SCHEMA_VERSION: u32 = 1;
+
+pub const VERSION_FIELD: &str = "config:version";
+
+/// Every field of the config hash. Commands must go through these instead of
+/// spelling out field names so reads and writes can't drift apart.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum ConfigField {
+    VerificationType,
+    VerificationRole,
+    UnverifiedRole,
+    GateChannel,
+    GateMessage,
+    /// Minutes new members have to verify in.
+    Timeout,
+    TimeoutAction,
+    QuarantineRole,
+    LoggingChannel,
+    /// Comma separated [`LogCategory::key`]s that aren't logged.
+    DisabledLogs,
+    /// Overrides [`ConfigField::LoggingChannel`] for one category.
+    LogChannel(LogCategory),
+}
+
+impl ConfigField {
+    pub const ALL: [ConfigField; 16] = [
+        ConfigField::VerificationType,
+        ConfigField::VerificationRole,
+        ConfigField::UnverifiedRole,
+        ConfigField::GateChannel,
+        ConfigField::GateMessage,
+        ConfigField::Timeout,
+        ConfigField::TimeoutAction,
+        ConfigField::QuarantineRole,
+        ConfigField::LoggingChannel,
+        ConfigField::DisabledLogs,
+        ConfigField::LogChannel(LogCategory::Joins),
+        ConfigField::LogChannel(LogCategory::Leaves),
+        ConfigField::LogChannel(LogCategory::VerificationPassed),
+        ConfigField::LogChannel(LogCategory::VerificationFailed),
+        ConfigField::LogChannel(LogCategory::TimeoutKicks),
+        ConfigField::LogChannel(LogCategory::ConfigChanges),
+    ];
+
+    /// The field name in the Redis hash.
+    pub const fn key(self) -> &'static str {
+        match self {
+            ConfigField::VerificationType => "verification:type",
+            ConfigField::VerificationRole => "verification:role",
+            ConfigField::UnverifiedRole => "verification:unverified_role",
+            ConfigField::GateChannel => "verification:channel",
+            ConfigField::GateMessage => "verification:message",
+            ConfigField::Timeout => "verification:timeout",
+            ConfigField::TimeoutAction => "verification:timeout_action",
+            ConfigField::QuarantineRole => "verification:quarantine_role",
+            ConfigField::LoggingChannel => "logging:channel",
+            ConfigField::DisabledLogs => "logging:disabled",
+            ConfigField::LogChannel(category) => match category {
+                LogCategory::Joins => "logging:channel:joins",
+                LogCategory::Leaves => "logging:channel:leaves",
+                LogCategory::VerificationPassed => "logging:channel:verification_passed",
+                LogCategory::VerificationFailed => "logging:channel:verification_failed",
+                LogCategory::TimeoutKicks => "logging:channel:timeout_kicks",
+                LogCategory::ConfigChanges => "logging:channel:config_changes",
+            },
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|field| field.key() == key)
+    }
+}
+
+/// The kinds of events the logger posts, each can be turned off or sent to
+/// its own channel.
+#[derive(Parse, Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum LogCategory {
+    Joins,
+    Leaves,
+    VerificationPassed,
+    VerificationFailed,
+    TimeoutKicks,
+    ConfigChanges,
+}
+
+impl LogCategory {
+    pub const ALL: [LogCategory; 6] = [
+        LogCategory::Joins,
+        LogCategory::Leaves,
+        LogCategory::VerificationPassed,
+        LogCategory::VerificationFailed,
+        LogCategory::TimeoutKicks,
+        LogCategory::ConfigChanges,
+    ];
+
+    /// The name stored in [`ConfigField::DisabledLogs`].
+    pub const fn key(self) -> &'static str {
+        match self {
+            LogCategory::Joins => "joins",
+            LogCategory::Leaves => "leaves",
+            LogCategory::VerificationPassed => "verification_passed",
+            LogCategory::VerificationFailed => "verification_failed",
+            LogCategory::TimeoutKicks => "timeout_kicks",
+            LogCategory::ConfigChanges => "config_changes",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|category| category.key() == key)
+    }
+
+    /// How the category is shown to users.
+    pub const fn name(self) -> &'static str {
+        match self {
+            LogCategory::Joins => "Joins",
+            LogCategory::Leaves => "Leaves",
+            LogCategory::VerificationPassed => "Verification passed",
+            LogCategory::VerificationFailed => "Verification failed",
+            LogCategory::TimeoutKicks => "Timeout kicks",
+            LogCategory::ConfigChanges => "Config changes",
+        }
+    }
+}
+
+#[derive(Parse, Debug, Clone, Eq, PartialEq)]
+pub enum VerificationType {
+    None,
+    Captcha,
+    Audio,
+}
+
+impl VerificationType {
+    pub const ALL: [VerificationType; 3] = [
+        VerificationType::None,
+        VerificationType::Captcha,
+        VerificationType::Audio,
+    ];
+}
+
+impl Default for VerificationType {
+    fn default() -> Self {
+        VerificationType::None
+    }
+}
+
+impl From<String> for VerificationType {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "None" => VerificationType::None,
+            "Captcha" => VerificationType::Captcha,
+            "Audio" => VerificationType::Audio,
+            _ => VerificationType::None,
+        }
+    }
+}
+
+impl ToString for VerificationType {
+    fn to_string(&self) -> String {
+        match self {
+            VerificationType::None => String::from("None"),
+            VerificationType::Captcha => String::from("Captcha"),
+            VerificationType::Audio => String::from("Audio"),
+        }
+    }
+}
+
+/// What happens to members who don't verify before the timeout.
+#[derive(Parse, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TimeoutAction {
+    Kick,
+    Quarantine,
+}
+
+impl Default for TimeoutAction {
+    fn default() -> Self {
+        TimeoutAction::Kick
+    }
+}
+
+impl From<String> for TimeoutAction {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "Quarantine" => TimeoutAction::Quarantine,
+            _ => TimeoutAction::Kick,
+        }
+    }
+}
+
+impl ToString for TimeoutAction {
+    fn to_string(&self) -> String {
+        match self {
+            TimeoutAction::Kick => String::from("Kick"),
+            TimeoutAction::Quarantine => String::from("Quarantine"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct GuildConfig {
+    pub verification_type: VerificationType,
+    pub verification_role: Option<Id<RoleMarker>>,
+    pub unverified_role: Option<Id<RoleMarker>>,
+    pub gate_channel: Option<Id<ChannelMarker>>,
+    pub gate_message: Option<Id<MessageMarker>>,
+    /// Minutes new members have to verify in, `None` to let them stay.
+    pub timeout: Option<u64>,
+    pub timeout_action: TimeoutAction,
+    /// Given instead of kicking when the action is quarantine.
+    pub quarantine_role: Option<Id<RoleMarker>>,
+    pub logging_channel: Option<Id<ChannelMarker>>,
+    pub disabled_logs: BTreeSet<LogCategory>,
+    pub log_channels: BTreeMap<LogCategory, Id<ChannelMarker>>,
+}
+
+pub fn key(guild_id: Id<GuildMarker>) -> String {
+    format!("config:{}", guild_id.get())
+}
+
+fn parse_id<T>(fields: &HashMap<String, String>, field: ConfigField) -> Option<Id<T>> {
+    fields
+        .get(field.key())
+        .and_then(|value| value.parse().ok())
+        .and_then(Id::new_checked)
+}
+
+fn version(fields: &HashMap<String, String>) -> u32 {
+    fields
+        .get(VERSION_FIELD)
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Fields renamed by each schema version, as `(legacy, canonical)` pairs.
+/// Index `n` upgrades a hash from version `n` to `n + 1`.
+const MIGRATIONS: [&[(&str, &str)]; SCHEMA_VERSION as usize] = [
+    // Before versioning `/verification type` and `/logging channel` wrote
+    // to fields nothing read from.
+    &[
+        ("type", "verification:type"),
+        ("channel", "logging:channel"),
+    ],
+];
+
+/// Brings a guild's hash up to [`SCHEMA_VERSION`]. Returns whether anything
+/// had to be changed. Values under a canonical field win over legacy ones.
+pub async fn migrate(conn: &mut Connection, guild_id: Id<GuildMarker>) -> Result<bool, RedisError> {
+    let fields: HashMap<String, String> = conn.hgetall(key(guild_id)).await?;
+    migrate_fields(conn, guild_id, &fields).await
+}
+
+/// The writes that bring a hash up to [`SCHEMA_VERSION`].
+#[derive(Debug, PartialEq, Eq)]
+struct MigrationPlan {
+    from: u32,
+    /// Fields to write, ending with the new [`VERSION_FIELD`].
+    set: Vec<(&'static str, String)>,
+    /// Legacy fields to remove.
+    delete: Vec<&'static str>,
+}
+
+/// Works out how to migrate `fields`, `None` when the hash is empty or
+/// already current.
+fn plan_migration(fields: &HashMap<String, String>) -> Option<MigrationPlan> {
+    let from = version(fields);
+    if from >= SCHEMA_VERSION || fields.is_empty() {
+        return None;
+    }
+
+    let mut plan = MigrationPlan {
+        from,
+        set: Vec::new(),
+        delete: Vec::new(),
+    };
+    for renames in &MIGRATIONS[from as usize..] {
+        for (legacy, canonical) in renames.iter() {
+            if let Some(value) = fields.get(*legacy) {
+                if !fields.contains_key(*canonical) {
+                    plan.set.push((*canonical, value.clone()));
+                }
+                plan.delete.push(*legacy);
+            }
+        }
+    }
+    plan.set.push((VERSION_FIELD, SCHEMA_VERSION.to_string()));
+    Some(plan)
+}
+
+async fn migrate_fields(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    fields: &HashMap<String, String>,
+) -> Result<bool, RedisError> {
+    let plan = match plan_migration(fields) {
+        Some(plan) => plan,
+        None => return Ok(false),
+    };
+
+    let mut pipe = deadpool_redis::redis::pipe();
+    pipe.atomic();
+    for (field, value) in &plan.set {
+        pipe.hset(key(guild_id), *field, value).ignore();
+    }
+    if !plan.delete.is_empty() {
+        pipe.hdel(key(guild_id), &plan.delete).ignore();
+    }
+    pipe.query_async::<_, ()>(conn).await?;
+
+    tracing::info!(
+        guild = guild_id.get(),
+        from = plan.from,
+        to = SCHEMA_VERSION,
+        "migrated guild config"
+    );
+    Ok(true)
+}
+
+/// Migrates every config hash in Redis, returning how many were changed.
+pub async fn migrate_all(conn: &mut Connection) -> Result<usize, RedisError> {
+    let keys: Vec<String> = {
+        let mut iter = conn.scan_match::<_, String>("config:*").await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        keys
+    };
+
+    let mut migrated = 0;
+    for guild_id in keys
+        .iter()
+        .filter_map(|key| key.strip_prefix("config:")?.parse().ok())
+        .filter_map(Id::new_checked)
+    {
+        if migrate(conn, guild_id).await? {
+            migrated += 1;
+        }
+    }
+    Ok(migrated)
+}
+
+impl GuildConfig {
+    /// Loads the config, migrating hashes written with an older schema first.
+    pub async fn load(
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+    ) -> Result<Self, RedisError> {
+        let mut fields: HashMap<String, String> = conn.hgetall(key(guild_id)).await?;
+        if migrate_fields(conn, guild_id, &fields).await? {
+            fields = conn.hgetall(key(guild_id)).await?;
+        }
+
+        Ok(Self {
+            verification_type: fields
+                .get(ConfigField::VerificationType.key())
+                .cloned()
+                .map(VerificationType::from)
+                .unwrap_or_default(),
+            verification_role: parse_id(&fields, ConfigField::VerificationRole),
+            unverified_role: parse_id(&fields, ConfigField::UnverifiedRole),
+            gate_channel: parse_id(&fields, ConfigField::GateChannel),
+            gate_message: parse_id(&fields, ConfigField::GateMessage),
+            timeout: fields
+                .get(ConfigField::Timeout.key())
+                .and_then(|minutes| minutes.parse().ok())
+                .filter(|minutes| *minutes > 0),
+            timeout_action: fields
+                .get(ConfigField::TimeoutAction.key())
+                .cloned()
+                .map(TimeoutAction::from)
+                .unwrap_or_default(),
+            quarantine_role: parse_id(&fields, ConfigField::QuarantineRole),
+            logging_channel: parse_id(&fields, ConfigField::LoggingChannel),
+            disabled_logs: fields
+                .get(ConfigField::DisabledLogs.key())
+                .map(|keys| keys.split(',').filter_map(LogCategory::from_key).collect())
+                .unwrap_or_default(),
+            log_channels: LogCategory::ALL
+                .into_iter()
+                .filter_map(|category| {
+                    parse_id(&fields, ConfigField::LogChannel(category))
+                        .map(|channel_id| (category, channel_id))
+                })
+                .collect(),
+        })
+    }
+
+    /// Where events of `category` are posted, `None` when the category is
+    /// turned off or there is nowhere to post it.
+    pub fn log_channel(&self, category: LogCategory) -> Option<Id<ChannelMarker>> {
+        if self.disabled_logs.contains(&category) {
+            return None;
+        }
+        self.log_channels
+            .get(&category)
+            .copied()
+            .or(self.logging_channel)
+    }
+
+    /// The value of a field as it is stored in Redis.
+    pub fn get(&self, field: ConfigField) -> Option<String> {
+        match field {
+            ConfigField::VerificationType => match self.verification_type {
+                VerificationType::None => None,
+                ref kind => Some(kind.to_string()),
+            },
+            ConfigField::VerificationRole => self.verification_role.map(|id| id.to_string()),
+            ConfigField::UnverifiedRole => self.unverified_role.map(|id| id.to_string()),
+            ConfigField::GateChannel => self.gate_channel.map(|id| id.to_string()),
+            ConfigField::GateMessage => self.gate_message.map(|id| id.to_string()),
+            ConfigField::Timeout => self.timeout.map(|minutes| minutes.to_string()),
+            ConfigField::TimeoutAction => match self.timeout_action {
+                TimeoutAction::Kick => None,
+                action => Some(action.to_string()),
+            },
+            ConfigField::QuarantineRole => self.quarantine_role.map(|id| id.to_string()),
+            ConfigField::LoggingChannel => self.logging_channel.map(|id| id.to_string()),
+            ConfigField::DisabledLogs => Some(
+                self.disabled_logs
+                    .iter()
+                    .map(|category| category.key())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            )
+            .filter(|keys| !keys.is_empty()),
+            ConfigField::LogChannel(category) => {
+                self.log_channels.get(&category).map(|id| id.to_string())
+            }
+        }
+    }
+
+    /// Writes every field, removing the ones that are unset, and marks the hash
+    /// as being on the current schema.
+    pub async fn save(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+    ) -> Result<(), RedisError> {
+        let (set, unset): (Vec<_>, Vec<_>) = ConfigField::ALL
+            .iter()
+            .map(|field| (field.key(), self.get(*field)))
+            .partition(|(_, value)| value.is_some());
+
+        let set: Vec<(&str, String)> = set
+            .into_iter()
+            .map(|(field, value)| (field, value.unwrap()))
+            .collect();
+        let unset: Vec<&str> = unset.into_iter().map(|(field, _)| field).collect();
+
+        if !set.is_empty() {
+            conn.hset_multiple::<_, _, _, ()>(key(guild_id), &set)
+                .await?;
+        }
+        if !unset.is_empty() {
+            conn.hdel::<_, _, ()>(key(guild_id), unset).await?;
+        }
+        conn.hset::<_, _, _, ()>(key(guild_id), VERSION_FIELD, SCHEMA_VERSION)
+            .await?;
+        Ok(())
+    }
+
+    /// Sets or, when `value` is `None`, removes a single field without
+    /// touching the others. Returns the previous value.
+    pub async fn patch(
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        field: ConfigField,
+        value: Option<String>,
+    ) -> Result<Option<String>, RedisError> {
+        let previous: Option<String> = conn.hget(key(guild_id), field.key()).await?;
+
+        match value {
+            Some(value) => {
+                conn.hset::<_, _, _, ()>(key(guild_id), field.key(), value)
+                    .await?
+            }
+            None => conn.hdel::<_, _, ()>(key(guild_id), field.key()).await?,
+        }
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn renames_legacy_fields_and_stamps_version() {
+        let plan = plan_migration(&hash(&[("type", "Captcha"), ("channel", "5")])).unwrap();
+
+        assert_eq!(plan.from, 0);
+        assert_eq!(
+            plan.set,
+            vec![
+                ("verification:type", String::from("Captcha")),
+                ("logging:channel", String::from("5")),
+                (VERSION_FIELD, SCHEMA_VERSION.to_string()),
+            ]
+        );
+        assert_eq!(plan.delete, vec!["type", "channel"]);
+    }
+
+    #[test]
+    fn canonical_field_wins_over_legacy() {
+        let plan = plan_migration(&hash(&[
+            ("type", "Captcha"),
+            ("verification:type", "Audio"),
+        ]))
+        .unwrap();
+
+        assert_eq!(plan.set, vec![(VERSION_FIELD, SCHEMA_VERSION.to_string())]);
+        assert_eq!(plan.delete, vec!["type"]);
+    }
+
+    #[test]
+    fn stamps_version_without_legacy_fields() {
+        let plan = plan_migration(&hash(&[("verification:type", "Audio")])).unwrap();
+
+        assert_eq!(plan.set, vec![(VERSION_FIELD, SCHEMA_VERSION.to_string())]);
+        assert!(plan.delete.is_empty());
+    }
+
+    #[test]
+    fn skips_empty_and_current_hashes() {
+        assert_eq!(plan_migration(&HashMap::new()), None);
+        assert_eq!(
+            plan_migration(&hash(&[
+                ("type", "Captcha"),
+                (VERSION_FIELD, &SCHEMA_VERSION.to_string()),
+            ])),
+            None
+        );
+    }
+}
@@ -1,15 +1,43 @@
+use std::env;
+
 use deadpool_redis::{Config, Pool, Runtime};
+use twilight_http::Client;
+use twilight_model::id::{marker::UserMarker, Id};
 
 #[derive(Clone)]
 pub struct Context {
     pub redis: Pool,
+    /// The bot's user id, used to look up its roles and permissions.
+    pub bot_id: Id<UserMarker>,
 }
 
 impl Context {
-    pub fn new<S: ToString>(redis_url: S) -> Self {
+    pub fn new<S: ToString>(redis_url: S, bot_id: Id<UserMarker>) -> Self {
         let cfg = Config::from_url(redis_url.to_string());
         let redis = cfg.create_pool(Some(Runtime::Tokio1)).unwrap();
 
-        Self { redis }
+        Self { redis, bot_id }
+    }
+}
+
+/// The bot's user id from `BOT_ID`, if it is set to a valid id. It matches
+/// the application id for most bots, but not for older applications that got
+/// a bot user later, those have to set it.
+pub fn env_bot_id() -> Option<Id<UserMarker>> {
+    let id = env::var("BOT_ID").ok()?;
+    match id.parse().ok().and_then(Id::new_checked) {
+        Some(id) => Some(id),
+        None => {
+            tracing::warn!("BOT_ID {:?} is not a valid id, ignoring it", id);
+            None
+        }
     }
 }
+
+/// Asks Discord for the bot's user id, for when `BOT_ID` isn't set and there
+/// is no application id to fall back to.
+pub async fn current_user_id(
+    http: &Client,
+) -> Result<Id<UserMarker>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(http.current_user().exec().await?.model().await?.id)
+}
@@ -0,0 +1,112 @@
+//! Deferred responses for handlers that may not finish within Discord's three
+//! second deadline.
+//!
+//! The acknowledgement is sent through the callback endpoint before the
+//! handler runs and its response replaces the placeholder once it's done. The
+//! function only returns after that, so the invocation stays alive for the
+//! whole follow-up.
+
+use std::future::Future;
+
+use twilight_http::client::InteractionClient;
+use twilight_model::{
+    channel::message::MessageFlags,
+    http::interaction::{InteractionResponse, InteractionResponseData, InteractionResponseType},
+    id::{marker::InteractionMarker, Id},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeferError {
+    #[error("Failed to acknowledge the interaction.")]
+    AckFailed(#[source] twilight_http::Error),
+
+    #[error("Failed to send the follow-up.")]
+    FollowUpFailed(#[source] twilight_http::Error),
+
+    #[error("The follow-up is not a valid message: {0}")]
+    InvalidFollowUp(String),
+}
+
+/// How a deferred interaction is acknowledged. Ephemerality can't be changed
+/// by the follow-up, so it has to be decided up front.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Defer {
+    /// Shows a "thinking" message that the follow-up replaces.
+    Message { ephemeral: bool },
+    /// Keeps the message a component is attached to, the follow-up edits it.
+    Update,
+}
+
+impl Defer {
+    pub fn response(self) -> InteractionResponse {
+        match self {
+            Defer::Message { ephemeral } => InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: Some(InteractionResponseData {
+                    flags: ephemeral.then_some(MessageFlags::EPHEMERAL),
+                    ..Default::default()
+                }),
+            },
+            Defer::Update => InteractionResponse {
+                kind: InteractionResponseType::DeferredUpdateMessage,
+                data: None,
+            },
+        }
+    }
+}
+
+/// Acknowledges the interaction, waits for `work` and edits the original
+/// response to what it returned. Responses without message data, like modals,
+/// can't be sent this way and are rejected.
+pub async fn run<F>(
+    client: &InteractionClient<'_>,
+    interaction_id: Id<InteractionMarker>,
+    token: &str,
+    defer: Defer,
+    work: F,
+) -> Result<(), DeferError>
+where
+    F: Future<Output = InteractionResponse>,
+{
+    client
+        .create_response(interaction_id, token, &defer.response())
+        .exec()
+        .await
+        .map_err(DeferError::AckFailed)?;
+
+    let response = work.await;
+    let data = match (response.kind, response.data) {
+        (
+            InteractionResponseType::ChannelMessageWithSource
+            | InteractionResponseType::UpdateMessage,
+            Some(data),
+        ) => data,
+        (kind, _) => {
+            return Err(DeferError::InvalidFollowUp(format!(
+                "{:?} responses can't be deferred",
+                kind
+            )))
+        }
+    };
+
+    let attachments = data.attachments.unwrap_or_default();
+    client
+        .update_response(token)
+        .content(data.content.as_deref())
+        .map_err(invalid)?
+        .embeds(data.embeds.as_deref())
+        .map_err(invalid)?
+        .components(data.components.as_deref())
+        .map_err(invalid)?
+        .attachments(&attachments)
+        .map_err(invalid)?
+        .exec()
+        .await
+        .map_err(DeferError::FollowUpFailed)?;
+
+    Ok(())
+}
+
+fn invalid(why: impl std::fmt::Display) -> DeferError {
+    DeferError::InvalidFollowUp(why.to_string())
+}
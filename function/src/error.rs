@@ -1,5 +1,71 @@
+use lambda_http::{Body, IntoResponse, Response};
+
+use crate::{deferred::DeferError, verification::VerificationError};
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Failed to deserialize from or serialize to JSON.")]
     JsonFailed(#[from] serde_json::Error),
+
+    #[error("Failed to send the interaction response.")]
+    ResponseFailed(#[from] twilight_http::Error),
+
+    #[error("Failed to build the HTTP response.")]
+    HttpFailed(#[from] lambda_http::http::Error),
+
+    #[error("Malformed request body: {0}")]
+    MalformedBody(String),
+
+    #[error("Missing or unreadable header `{0}`.")]
+    MissingHeader(&'static str),
+
+    #[error("{0}")]
+    InvalidSignature(#[from] VerificationError),
+
+    #[error("Failed to get a Redis connection.")]
+    RedisFailed(#[from] deadpool_redis::PoolError),
+
+    #[error("Unsupported interaction type.")]
+    UnsupportedInteraction,
+
+    #[error("{0}")]
+    DeferFailed(#[from] DeferError),
+}
+
+impl Error {
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::MalformedBody(_) | Error::UnsupportedInteraction => 400,
+            Error::InvalidSignature(
+                VerificationError::RedisFailed(_) | VerificationError::NoPublicKeys,
+            ) => 500,
+            Error::MissingHeader(_) | Error::InvalidSignature(_) => 401,
+            Error::JsonFailed(_)
+            | Error::ResponseFailed(_)
+            | Error::HttpFailed(_)
+            | Error::RedisFailed(_)
+            | Error::DeferFailed(_) => 500,
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        let body = if status >= 500 {
+            // Internal details stay in the logs, the id ties the reply to them.
+            let correlation_id = uuid::Uuid::new_v4();
+            tracing::error!(%correlation_id, error = ?self, "failed to handle interaction");
+            format!("Internal error, correlation id {}", correlation_id)
+        } else {
+            tracing::warn!(status, "rejected request: {}", self);
+            self.to_string()
+        };
+
+        Response::builder()
+            .status(status)
+            .header("content-type", "text/plain;charset=UTF-8")
+            .body(Body::Text(body))
+            .expect("static response parts are valid")
+    }
 }
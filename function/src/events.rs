@@ -0,0 +1,351 @@
+//! Reacts to the gateway events `twilight-dispatch` publishes to Redis.
+//!
+//! Every message on the channel is a gateway dispatch payload, only the
+//! `t` and `d` fields are used.
+
+use std::sync::Arc;
+
+use deadpool_redis::{redis::RedisError, Connection, PoolError};
+use serde::{de::DeserializeSeed, Deserialize};
+use twilight_http::{
+    api_error::{ApiError, GeneralApiError},
+    error::ErrorType,
+    request::AuditLogReason,
+    Client,
+};
+use twilight_model::{
+    gateway::{
+        event::{DispatchEventWithTypeDeserializer, Event},
+        payload::incoming::{MemberAdd, MemberRemove},
+    },
+    id::{
+        marker::{GuildMarker, UserMarker},
+        Id,
+    },
+};
+
+use crate::{
+    cache::{self, CacheError},
+    config::{GuildConfig, TimeoutAction, VerificationType},
+    logger::{self, Logger, MemberEvent},
+    roles::{RoleError, RoleService},
+    timeouts, Context,
+};
+
+/// Channel `twilight-dispatch` publishes to unless `DISPATCH_CHANNEL` is set.
+pub const DEFAULT_CHANNEL: &str = "gateway";
+
+/// Event types the worker acts on, anything else is skipped before parsing.
+const HANDLED_EVENTS: &[&str] = &["GUILD_MEMBER_ADD", "GUILD_MEMBER_REMOVE"];
+
+/// Discord's error code for members that aren't in the guild.
+const UNKNOWN_MEMBER: u64 = 10007;
+
+/// Discord's error code for actions the bot lacks a permission for.
+const MISSING_PERMISSIONS: u64 = 50013;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EventError {
+    #[error("Failed to get a Redis connection.")]
+    PoolFailed(#[from] PoolError),
+
+    #[error("Failed to read from or write to Redis.")]
+    RedisFailed(#[from] RedisError),
+
+    #[error("{0}")]
+    RoleFailed(#[from] RoleError),
+
+    #[error("Failed to read the guild cache.")]
+    CacheFailed(#[from] CacheError),
+
+    #[error("Failed to send a Discord request.")]
+    RequestFailed(#[from] twilight_http::Error),
+
+    #[error("Failed to deserialize a Discord response.")]
+    DeserializeFailed(#[from] twilight_http::response::DeserializeBodyError),
+
+    #[error("The request is not valid: {0}")]
+    InvalidRequest(String),
+
+    #[error("The timeout action is quarantine but no quarantine role is set.")]
+    NoQuarantineRole,
+}
+
+impl EventError {
+    /// Whether trying again can't help until someone changes the guild's
+    /// config or the bot's permissions.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            EventError::NoQuarantineRole | EventError::InvalidRequest(_) => true,
+            EventError::RoleFailed(
+                RoleError::NotConfigured
+                | RoleError::MissingManageRoles(_)
+                | RoleError::AboveBot(_),
+            ) => true,
+            EventError::RequestFailed(why)
+            | EventError::RoleFailed(RoleError::RequestFailed(why)) => {
+                has_error_code(why, MISSING_PERMISSIONS) || has_error_code(why, UNKNOWN_MEMBER)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    t: Option<String>,
+    #[serde(default)]
+    d: serde_json::Value,
+}
+
+/// Whether Discord rejected the request with the error `code`.
+fn has_error_code(why: &twilight_http::Error, code: u64) -> bool {
+    matches!(
+        why.kind(),
+        ErrorType::Response {
+            error: ApiError::General(GeneralApiError { code: actual, .. }),
+            ..
+        } if *actual == code
+    )
+}
+
+/// Whether Discord answered that the member isn't in the guild.
+fn is_unknown_member(why: &twilight_http::Error) -> bool {
+    has_error_code(why, UNKNOWN_MEMBER)
+}
+
+/// Parses a published payload, `None` for events the worker doesn't handle.
+pub fn parse(payload: &str) -> Result<Option<Event>, serde_json::Error> {
+    let payload: Payload = serde_json::from_str(payload)?;
+    let kind = match payload.t {
+        Some(kind) if HANDLED_EVENTS.contains(&kind.as_str()) => kind,
+        _ => return Ok(None),
+    };
+
+    let event = DispatchEventWithTypeDeserializer::new(&kind).deserialize(payload.d)?;
+    Ok(Some(Event::from(event)))
+}
+
+pub struct EventHandler {
+    http: Arc<Client>,
+    data: Context,
+}
+
+impl EventHandler {
+    pub fn new(http: Arc<Client>, data: Context) -> Self {
+        Self { http, data }
+    }
+
+    pub async fn handle(&self, event: Event) -> Result<(), EventError> {
+        match event {
+            Event::MemberAdd(member) => self.member_add(*member).await,
+            Event::MemberRemove(member) => self.member_remove(member).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Logs the join and, when verification is enabled, starts it: the
+    /// member gets the unverified role, a DM pointing at the gate and a
+    /// deadline when the guild has a timeout set.
+    async fn member_add(&self, member: MemberAdd) -> Result<(), EventError> {
+        if member.user.bot {
+            return Ok(());
+        }
+        let (guild_id, user) = (member.guild_id, &member.user);
+
+        let mut conn = self.data.redis.get().await?;
+        logger::record_join(&mut conn, guild_id, user.id).await?;
+        // A broken logging channel shouldn't keep members from verifying.
+        if let Err(why) = Logger::new(&self.http)
+            .log(&mut conn, guild_id, MemberEvent::Joined { user })
+            .await
+        {
+            tracing::warn!(guild = guild_id.get(), error = ?why, "failed to log member join");
+        }
+
+        let config = GuildConfig::load(&mut conn, guild_id).await?;
+        if config.verification_type == VerificationType::None {
+            return Ok(());
+        }
+
+        if let Some(minutes) = config.timeout {
+            // Stored configs predate the limit on minutes, so don't trust them.
+            let due = logger::now().saturating_add(minutes.saturating_mul(60));
+            timeouts::schedule(&mut conn, guild_id, user.id, due).await?;
+        }
+
+        if let Some(role_id) = config.unverified_role {
+            RoleService::new(&self.http, self.data.bot_id)
+                .add(&mut conn, guild_id, user.id, role_id)
+                .await?;
+        }
+
+        if let (Some(channel_id), Some(message_id)) = (config.gate_channel, config.gate_message) {
+            let content = format!(
+                "Welcome! Verify yourself at https://discord.com/channels/{}/{}/{} to get access to the server.",
+                guild_id, channel_id, message_id
+            );
+            // Members can turn DMs off, that shouldn't fail the rest.
+            if let Err(why) = self.direct_message(user.id, &content).await {
+                tracing::info!(user = user.id.get(), error = ?why, "could not DM the gate link");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops tracking the member and logs that they left.
+    async fn member_remove(&self, member: MemberRemove) -> Result<(), EventError> {
+        let mut conn = self.data.redis.get().await?;
+        logger::forget(&mut conn, member.guild_id, member.user.id).await?;
+        timeouts::cancel(&mut conn, member.guild_id, member.user.id).await?;
+        if let Err(why) = Logger::new(&self.http)
+            .log(
+                &mut conn,
+                member.guild_id,
+                MemberEvent::Left { user: &member.user },
+            )
+            .await
+        {
+            tracing::warn!(guild = member.guild_id.get(), error = ?why, "failed to log member leave");
+        }
+        Ok(())
+    }
+
+    /// Applies the timeout action to every member whose deadline passed,
+    /// returning how many were handled. Failures are retried after
+    /// [`timeouts::RETRY_DELAY`] unless they are [permanent], those are
+    /// logged and dropped.
+    ///
+    /// [permanent]: EventError::is_permanent
+    pub async fn process_timeouts(&self) -> Result<usize, EventError> {
+        let mut conn = self.data.redis.get().await?;
+        let expired = timeouts::claim_expired(&mut conn, logger::now()).await?;
+
+        let mut handled = 0;
+        for (guild_id, user_id) in expired {
+            match self.expire(&mut conn, guild_id, user_id).await {
+                Ok(()) => handled += 1,
+                Err(why) if why.is_permanent() => {
+                    tracing::warn!(
+                        guild = guild_id.get(),
+                        user = user_id.get(),
+                        error = ?why,
+                        "dropped verification timeout that can't be applied"
+                    );
+                }
+                Err(why) => {
+                    tracing::warn!(
+                        guild = guild_id.get(),
+                        user = user_id.get(),
+                        error = ?why,
+                        "failed to apply verification timeout, retrying later"
+                    );
+                    // The deadline was claimed, so it's gone unless it's put back.
+                    let retry = logger::now() + timeouts::RETRY_DELAY;
+                    if let Err(why) = timeouts::schedule(&mut conn, guild_id, user_id, retry).await
+                    {
+                        tracing::error!(
+                            guild = guild_id.get(),
+                            user = user_id.get(),
+                            error = ?why,
+                            "dropped verification timeout"
+                        );
+                    }
+                }
+            }
+        }
+        Ok(handled)
+    }
+
+    /// Kicks or quarantines a member that didn't verify in time. Members that
+    /// verified, left or joined a guild that since turned verification off
+    /// are left alone. Members missing from the cache are looked up instead.
+    async fn expire(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<(), EventError> {
+        let config = GuildConfig::load(conn, guild_id).await?;
+        let verification_role = match config.verification_role {
+            Some(role_id) if config.verification_type != VerificationType::None => role_id,
+            _ => return Ok(()),
+        };
+        let roles = match cache::member_roles(conn, guild_id, user_id).await? {
+            Some(roles) => roles,
+            None => match self.http.guild_member(guild_id, user_id).exec().await {
+                Ok(response) => response.model().await?.roles,
+                Err(why) if is_unknown_member(&why) => return Ok(()),
+                Err(why) => return Err(why.into()),
+            },
+        };
+        if roles.contains(&verification_role) {
+            return Ok(());
+        }
+
+        match config.timeout_action {
+            TimeoutAction::Kick => {
+                let kicked = self
+                    .http
+                    .remove_guild_member(guild_id, user_id)
+                    .reason("Did not verify in time")
+                    .map_err(|why| EventError::InvalidRequest(why.to_string()))?
+                    .exec()
+                    .await;
+                match kicked {
+                    Ok(_) => {}
+                    // The cached member was stale, they already left.
+                    Err(why) if is_unknown_member(&why) => {
+                        logger::forget(conn, guild_id, user_id).await?;
+                        return Ok(());
+                    }
+                    Err(why) => return Err(why.into()),
+                }
+            }
+            TimeoutAction::Quarantine => {
+                let role_id = config.quarantine_role.ok_or(EventError::NoQuarantineRole)?;
+                RoleService::new(&self.http, self.data.bot_id)
+                    .add(conn, guild_id, user_id, role_id)
+                    .await?;
+            }
+        }
+        logger::forget(conn, guild_id, user_id).await?;
+
+        let user = self.http.user(user_id).exec().await?.model().await?;
+        if let Err(why) = Logger::new(&self.http)
+            .log(
+                conn,
+                guild_id,
+                MemberEvent::TimedOut {
+                    user: &user,
+                    action: config.timeout_action,
+                },
+            )
+            .await
+        {
+            tracing::warn!(guild = guild_id.get(), error = ?why, "failed to log verification timeout");
+        }
+        Ok(())
+    }
+
+    async fn direct_message(
+        &self,
+        user_id: Id<UserMarker>,
+        content: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let channel = self
+            .http
+            .create_private_channel(user_id)
+            .exec()
+            .await?
+            .model()
+            .await?;
+        self.http
+            .create_message(channel.id)
+            .content(content)?
+            .exec()
+            .await?;
+        Ok(())
+    }
+}
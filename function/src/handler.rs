@@ -0,0 +1,290 @@
+use std::{future::Future, sync::Arc};
+
+use lambda_http::{Body, Error, IntoResponse, Request, RequestExt, Response};
+use lazy_static::lazy_static;
+use twilight_model::{
+    application::interaction::Interaction,
+    http::interaction::{InteractionResponse, InteractionResponseType},
+    id::{marker::InteractionMarker, Id},
+};
+use zephyrus::{
+    command::Command,
+    prelude::*,
+    twilight_exports::{ApplicationCommand, InteractionResponseData},
+};
+
+use crate::{
+    components::{Components, CustomId},
+    deferred::{self, Defer},
+    path::CommandPath,
+    verification::{self, verify_signature, verify_timestamp, verify_unseen, ApplicationKey},
+    Context, CustomError,
+};
+
+lazy_static! {
+    pub static ref PUBLIC_KEYS: Vec<ApplicationKey> =
+        verification::keys_from_env().expect("Invalid PUBLIC_KEYS");
+}
+
+/// This is the main body for the function.
+/// Write your code inside it.
+/// There are some code example in the following URLs:
+// / - https://github.com/awslabs/aws-lambda-rust-runtime/tree/main/lambda-http/examples
+pub async fn function_handler(
+    event: Request,
+    framework: Arc<Framework<Context>>,
+    components: Arc<Components>,
+) -> Result<Response<Body>, Error> {
+    Ok(match handle_request(event, framework, components).await {
+        Ok(response) => response.into_response(),
+        Err(why) => why.into_response(),
+    })
+}
+
+async fn handle_request(
+    event: Request,
+    framework: Arc<Framework<Context>>,
+    components: Arc<Components>,
+) -> Result<Response<String>, CustomError> {
+    let body = match event.body() {
+        Body::Text(body) => body,
+        _ => {
+            return Err(CustomError::MalformedBody(
+                "expected a text body".to_string(),
+            ))
+        }
+    };
+
+    // Verify the signature before looking at the payload
+    let signature = header(&event, "x-signature-ed25519")?;
+    let timestamp = header(&event, "x-signature-timestamp")?;
+    let application = verify_signature(&PUBLIC_KEYS, signature, timestamp, body)?;
+    verify_timestamp(timestamp)?;
+    tracing::debug!(application = %application.name, "verified request signature");
+
+    let interaction = event
+        .payload::<Interaction>()
+        .map_err(|why| CustomError::MalformedBody(why.to_string()))?
+        .ok_or_else(|| CustomError::MalformedBody("missing interaction payload".to_string()))?;
+
+    let (interaction_id, _) = interaction_token(&interaction)?;
+    let mut conn = framework.data.redis.get().await?;
+    verify_unseen(&mut conn, interaction_id).await?;
+    drop(conn);
+
+    actual_handler(interaction, framework, components).await
+}
+
+fn header<'a>(event: &'a Request, name: &'static str) -> Result<&'a str, CustomError> {
+    event
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(CustomError::MissingHeader(name))
+}
+
+/// Looks up the command an interaction is for and narrows its options down
+/// to the ones passed to that command.
+fn get_command<'a>(
+    s: &'a Framework<Context>,
+    interaction: &mut ApplicationCommand,
+) -> Option<&'a Command<Context>> {
+    let path = CommandPath::resolve(&interaction.data.name, &interaction.data.options);
+    let command = match path.names.as_slice() {
+        [name] => s.commands.get(*name)?,
+        [group, name] => s.groups.get(*group)?.kind.as_simple()?.get(*name)?,
+        [group, subgroup, name] => s
+            .groups
+            .get(*group)?
+            .kind
+            .as_group()?
+            .get(*subgroup)?
+            .subcommands
+            .get(*name)?,
+        _ => return None,
+    };
+    let options = path.options.to_vec();
+    interaction.data.options = options;
+    Some(command)
+}
+
+fn interaction_token(
+    interaction: &Interaction,
+) -> Result<(Id<InteractionMarker>, String), CustomError> {
+    Ok(match interaction {
+        Interaction::Ping(i) => (i.id, i.token.clone()),
+        Interaction::ApplicationCommand(i) => (i.id, i.token.clone()),
+        Interaction::ApplicationCommandAutocomplete(i) => (i.id, i.token.clone()),
+        Interaction::MessageComponent(i) => (i.id, i.token.clone()),
+        Interaction::ModalSubmit(i) => (i.id, i.token.clone()),
+        _ => return Err(CustomError::UnsupportedInteraction),
+    })
+}
+
+/// The name a command is registered under in [`Components::deferred_commands`],
+/// e.g. `"verification setup"`.
+fn command_path(command: &ApplicationCommand) -> String {
+    CommandPath::resolve(&command.data.name, &command.data.options).to_string()
+}
+
+async fn run_command(
+    framework: &Framework<Context>,
+    cmd: &Command<Context>,
+    command: ApplicationCommand,
+) -> InteractionResponse {
+    let http_client = &framework.http_client;
+    let interaction_client = http_client.inner().interaction(framework.application_id);
+    let context = SlashContext {
+        http_client,
+        interaction_client,
+        data: &framework.data,
+        interaction: command,
+        application_id: framework.application_id,
+    };
+
+    let execute = if let Some(before) = &framework.before {
+        (before.0)(&context, cmd.name).await
+    } else {
+        true
+    };
+
+    if execute {
+        let result = (cmd.fun)(&context).await;
+
+        match result {
+            Ok(inner) => inner,
+            Err(why) => InteractionResponse {
+                kind: InteractionResponseType::ChannelMessageWithSource,
+                data: Some(InteractionResponseData {
+                    content: Some(format!("{}", why)),
+                    ..Default::default()
+                }),
+            },
+        }
+    } else {
+        InteractionResponse {
+            kind: InteractionResponseType::ChannelMessageWithSource,
+            data: Some(InteractionResponseData {
+                content: Some("Command is disabled".to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Awaits `work` directly, or behind a deferred acknowledgement when `defer`
+/// is set. `None` means the response has already been sent.
+async fn respond<F>(
+    framework: &Framework<Context>,
+    interaction_id: Id<InteractionMarker>,
+    token: &str,
+    defer: Option<Defer>,
+    work: F,
+) -> Result<Option<InteractionResponse>, CustomError>
+where
+    F: Future<Output = InteractionResponse>,
+{
+    match defer {
+        Some(defer) => {
+            let client = framework
+                .http_client
+                .inner()
+                .interaction(framework.application_id);
+            deferred::run(&client, interaction_id, token, defer, work).await?;
+            Ok(None)
+        }
+        None => Ok(Some(work.await)),
+    }
+}
+
+async fn actual_handler<'a>(
+    interaction: Interaction,
+    framework: Arc<Framework<Context>>,
+    components: Arc<Components>,
+) -> Result<Response<String>, CustomError> {
+    let (interaction_id, token) = interaction_token(&interaction)?;
+    let resp: Option<InteractionResponse> = match interaction {
+        Interaction::Ping(_) => Some(InteractionResponse {
+            kind: InteractionResponseType::Pong,
+            data: None,
+        }),
+
+        Interaction::ApplicationCommand(mut command) => {
+            let defer = components
+                .deferred_commands
+                .get(command_path(&command).as_str())
+                .copied();
+            if let Some(cmd) = get_command(&framework, &mut command) {
+                let work = run_command(&framework, cmd, *command);
+                respond(&framework, interaction_id, &token, defer, work).await?
+            } else {
+                Some(InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        content: Some("Command not found".to_string()),
+                        ..Default::default()
+                    }),
+                })
+            }
+        }
+
+        Interaction::MessageComponent(component) => {
+            let defer = components.buttons.defer_for(component.custom_id());
+            let work = components.buttons.handle(
+                framework.http_client.inner(),
+                &framework.data,
+                framework.application_id,
+                *component,
+            );
+            respond(&framework, interaction_id, &token, defer, work).await?
+        }
+
+        Interaction::ModalSubmit(modal) => {
+            let defer = components.modals.defer_for(modal.custom_id());
+            let work = components.modals.handle(
+                framework.http_client.inner(),
+                &framework.data,
+                framework.application_id,
+                *modal,
+            );
+            respond(&framework, interaction_id, &token, defer, work).await?
+        }
+
+        Interaction::ApplicationCommandAutocomplete(command) => Some(
+            components
+                .autocomplete
+                .handle(&framework.data, *command)
+                .await?,
+        ),
+        _ => return Err(CustomError::UnsupportedInteraction),
+    };
+
+    let resp = match resp {
+        Some(resp) => resp,
+        None => return Ok(Response::builder().status(202).body(String::new())?),
+    };
+
+    // Files can't be part of the webhook reply, so responses carrying them are
+    // sent through the callback endpoint and the webhook is acknowledged empty.
+    if resp
+        .data
+        .as_ref()
+        .and_then(|data| data.attachments.as_ref())
+        .map_or(false, |attachments| !attachments.is_empty())
+    {
+        framework
+            .http_client
+            .inner()
+            .interaction(framework.application_id)
+            .create_response(interaction_id, &token, &resp)
+            .exec()
+            .await?;
+
+        return Ok(Response::builder().status(202).body(String::new())?);
+    }
+
+    Ok(Response::builder()
+        .status(200)
+        .header("content-type", "application/json;charset=UTF-8")
+        .body(serde_json::to_string(&resp)?)?)
+}
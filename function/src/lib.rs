@@ -0,0 +1,89 @@
+pub mod audit;
+pub mod autocomplete;
+pub mod cache;
+pub mod captcha;
+pub mod commands;
+pub mod components;
+pub mod config;
+pub mod context;
+pub mod deferred;
+pub mod error;
+pub mod events;
+pub mod handler;
+pub mod logger;
+pub mod path;
+pub mod registration;
+pub mod roles;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod timeouts;
+pub mod verification;
+
+use std::{collections::HashMap, sync::Arc};
+
+use autocomplete::AutocompleteRouter;
+use components::{ComponentRouter, Components};
+pub use context::Context;
+use deferred::Defer;
+pub use error::Error as CustomError;
+use twilight_http::Client;
+use twilight_model::id::{marker::ApplicationMarker, Id};
+use zephyrus::prelude::*;
+
+/// Builds the command tree. Shared by the function and the command
+/// registration binary so they can't disagree about what is registered.
+pub fn framework(
+    http_client: Arc<Client>,
+    application_id: Id<ApplicationMarker>,
+    context: Context,
+) -> Framework<Context> {
+    Framework::builder(http_client, application_id, context)
+        .group(|g| {
+            g.name("verification")
+                .description("Configuration for member verification")
+                .add_command(commands::verification::typ)
+                .add_command(commands::verification::role)
+                .add_command(commands::verification::setup)
+                .add_command(commands::verification::unverified)
+                .add_command(commands::verification::timeout)
+                .add_command(commands::verification::status)
+        })
+        .group(|g| {
+            g.name("logging")
+                .description("Configuration for how the bot will log member events")
+                .add_command(commands::logging::channel)
+                .add_command(commands::logging::enable)
+                .add_command(commands::logging::disable)
+                .add_command(commands::logging::history)
+        })
+        .build()
+}
+
+/// Builds the routers for components, modals and autocomplete. Anything that
+/// generates a captcha or makes several Discord requests is deferred.
+pub fn components() -> Components {
+    let ephemeral = Defer::Message { ephemeral: true };
+    Components {
+        buttons: ComponentRouter::new()
+            .route_deferred("verify:start", ephemeral, components::verification::start)
+            .route_deferred("verify:retry", ephemeral, components::verification::start)
+            .route_deferred("verify:replay", ephemeral, components::verification::replay)
+            .route("verify:answer", components::verification::answer),
+        modals: ComponentRouter::new().route_deferred(
+            "verify:submit",
+            ephemeral,
+            components::verification::submit,
+        ),
+        autocomplete: AutocompleteRouter::new()
+            .provider(
+                "verification type",
+                "choice",
+                autocomplete::verification_types,
+            )
+            .provider("logging history", "field", autocomplete::config_fields),
+        deferred_commands: HashMap::from([
+            ("verification setup", Defer::Message { ephemeral: false }),
+            ("verification status", Defer::Message { ephemeral: false }),
+        ]),
+    }
+}
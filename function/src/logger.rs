@@ -0,0 +1,303 @@
+//! Posts member joins and verification outcomes to the guild's logging
+//! channel.
+//!
+//! When a member joined and how often they tried to verify is kept in the
+//! `verification:{guild}:{user}` hash so the outcome can report it.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use deadpool_redis::{
+    redis::{AsyncCommands, RedisError},
+    Connection,
+};
+use twilight_http::Client;
+use twilight_model::{
+    channel::embed::Embed,
+    datetime::Timestamp,
+    id::{
+        marker::{GuildMarker, UserMarker},
+        Id,
+    },
+    user::User,
+};
+use twilight_util::builder::embed::{
+    EmbedAuthorBuilder, EmbedBuilder, EmbedFieldBuilder, EmbedFooterBuilder, ImageSource,
+};
+
+use crate::config::{GuildConfig, LogCategory, TimeoutAction};
+
+/// How long join tracking is kept for members that never finish verifying.
+const TRACKING_TTL: usize = 7 * 24 * 60 * 60;
+
+/// Milliseconds between the Unix epoch and the first second of 2015.
+const DISCORD_EPOCH: u64 = 1_420_070_400_000;
+
+const COLOUR_JOINED: u32 = 0x5865F2;
+const COLOUR_LEFT: u32 = 0x99AAB5;
+const COLOUR_PASSED: u32 = 0x57F287;
+const COLOUR_FAILED: u32 = 0xED4245;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogError {
+    #[error("Failed to read the logging config.")]
+    RedisFailed(#[from] RedisError),
+
+    #[error("Failed to post to the logging channel.")]
+    RequestFailed(#[from] twilight_http::Error),
+
+    #[error("The log message is not valid: {0}")]
+    InvalidMessage(String),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+}
+
+pub enum MemberEvent<'a> {
+    Joined {
+        user: &'a User,
+    },
+    Left {
+        user: &'a User,
+    },
+    Verification {
+        user: &'a User,
+        outcome: Outcome,
+        attempts: u64,
+        /// Time since the member joined, `None` when the join wasn't seen.
+        time_taken: Option<Duration>,
+    },
+    /// The member didn't verify before the guild's timeout.
+    TimedOut {
+        user: &'a User,
+        action: TimeoutAction,
+    },
+}
+
+impl MemberEvent<'_> {
+    pub fn category(&self) -> LogCategory {
+        match self {
+            MemberEvent::Joined { .. } => LogCategory::Joins,
+            MemberEvent::Left { .. } => LogCategory::Leaves,
+            MemberEvent::Verification {
+                outcome: Outcome::Passed,
+                ..
+            } => LogCategory::VerificationPassed,
+            MemberEvent::Verification {
+                outcome: Outcome::Failed,
+                ..
+            } => LogCategory::VerificationFailed,
+            MemberEvent::TimedOut { .. } => LogCategory::TimeoutKicks,
+        }
+    }
+}
+
+fn tracking_key(guild_id: Id<GuildMarker>, user_id: Id<UserMarker>) -> String {
+    format!("verification:{}:{}", guild_id.get(), user_id.get())
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// Starts tracking a member that just joined, forgetting earlier attempts.
+pub async fn record_join(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<(), RedisError> {
+    let key = tracking_key(guild_id, user_id);
+    deadpool_redis::redis::pipe()
+        .atomic()
+        .del(&key)
+        .ignore()
+        .hset(&key, "joined_at", now())
+        .ignore()
+        .expire(&key, TRACKING_TTL)
+        .ignore()
+        .query_async(conn)
+        .await
+}
+
+/// Counts a verification attempt, returning how many there have been and how
+/// long ago the member joined.
+pub async fn record_attempt(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<(u64, Option<Duration>), RedisError> {
+    let key = tracking_key(guild_id, user_id);
+    let (attempts, joined_at): (u64, Option<u64>) = deadpool_redis::redis::pipe()
+        .atomic()
+        .hincr(&key, "attempts", 1)
+        .hget(&key, "joined_at")
+        .expire(&key, TRACKING_TTL)
+        .ignore()
+        .query_async(conn)
+        .await?;
+
+    let time_taken =
+        joined_at.map(|joined_at| Duration::from_secs(now().saturating_sub(joined_at)));
+    Ok((attempts, time_taken))
+}
+
+/// Stops tracking a member once they are verified or have left.
+pub async fn forget(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<(), RedisError> {
+    conn.del(tracking_key(guild_id, user_id)).await
+}
+
+pub struct Logger<'a> {
+    http: &'a Client,
+}
+
+impl<'a> Logger<'a> {
+    pub fn new(http: &'a Client) -> Self {
+        Self { http }
+    }
+
+    /// Posts the event to the channel its category is logged to, doing
+    /// nothing when the category is turned off or no channel is set.
+    pub async fn log(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        event: MemberEvent<'_>,
+    ) -> Result<(), LogError> {
+        self.post(conn, guild_id, event.category(), embed(&event)?)
+            .await
+    }
+
+    /// Posts an embed under `category`, for events that aren't about a
+    /// member's verification.
+    pub async fn post(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        category: LogCategory,
+        embed: Embed,
+    ) -> Result<(), LogError> {
+        let config = GuildConfig::load(conn, guild_id).await?;
+        let channel_id = match config.log_channel(category) {
+            Some(channel_id) => channel_id,
+            None => return Ok(()),
+        };
+
+        self.http
+            .create_message(channel_id)
+            .embeds(&[embed])
+            .map_err(invalid)?
+            .exec()
+            .await?;
+        Ok(())
+    }
+}
+
+fn embed(event: &MemberEvent<'_>) -> Result<Embed, LogError> {
+    let user = match event {
+        MemberEvent::Joined { user }
+        | MemberEvent::Left { user }
+        | MemberEvent::Verification { user, .. }
+        | MemberEvent::TimedOut { user, .. } => user,
+    };
+
+    let created = (user.id.get() >> 22) + DISCORD_EPOCH;
+    let mut builder = EmbedBuilder::new()
+        .author(
+            EmbedAuthorBuilder::new(format!("{}#{:04}", user.name, user.discriminator))
+                .icon_url(ImageSource::url(avatar_url(user)).map_err(invalid)?),
+        )
+        .thumbnail(ImageSource::url(avatar_url(user)).map_err(invalid)?)
+        .field(EmbedFieldBuilder::new("User", format!("<@{}>", user.id)).inline())
+        .field(
+            EmbedFieldBuilder::new("Account created", format!("<t:{}:R>", created / 1000)).inline(),
+        )
+        .footer(EmbedFooterBuilder::new(format!("User ID: {}", user.id)))
+        .timestamp(Timestamp::from_secs(now() as i64).map_err(invalid)?);
+
+    builder = match event {
+        MemberEvent::Joined { .. } => builder.title("Member joined").color(COLOUR_JOINED),
+        MemberEvent::Left { .. } => builder.title("Member left").color(COLOUR_LEFT),
+        MemberEvent::TimedOut { action, .. } => builder
+            .title(match action {
+                TimeoutAction::Kick => "Kicked for not verifying",
+                TimeoutAction::Quarantine => "Quarantined for not verifying",
+            })
+            .color(COLOUR_FAILED),
+        MemberEvent::Verification {
+            outcome,
+            attempts,
+            time_taken,
+            ..
+        } => {
+            let (title, colour) = match outcome {
+                Outcome::Passed => ("Member verified", COLOUR_PASSED),
+                Outcome::Failed => ("Verification failed", COLOUR_FAILED),
+            };
+            builder
+                .title(title)
+                .color(colour)
+                .field(EmbedFieldBuilder::new("Attempts", attempts.to_string()).inline())
+                .field(
+                    EmbedFieldBuilder::new(
+                        "Time since joining",
+                        time_taken.map_or_else(|| String::from("Unknown"), format_duration),
+                    )
+                    .inline(),
+                )
+        }
+    };
+
+    builder
+        .validate()
+        .map(|builder| builder.build())
+        .map_err(invalid)
+}
+
+fn invalid(why: impl std::fmt::Display) -> LogError {
+    LogError::InvalidMessage(why.to_string())
+}
+
+fn avatar_url(user: &User) -> String {
+    match &user.avatar {
+        Some(hash) => format!(
+            "https://cdn.discordapp.com/avatars/{}/{}.png",
+            user.id, hash
+        ),
+        None => format!(
+            "https://cdn.discordapp.com/embed/avatars/{}.png",
+            user.discriminator % 5
+        ),
+    }
+}
+
+/// The largest two units of a duration, e.g. `2h 5m`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        (secs / 86_400, "d"),
+        (secs / 3_600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (secs % 60, "s"),
+    ];
+
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+
+    if parts.is_empty() {
+        String::from("0s")
+    } else {
+        parts.join(" ")
+    }
+}
@@ -0,0 +1,81 @@
+//! Resolves which command an interaction is for by walking down its
+//! subcommand groups and subcommands. Commands and autocomplete interactions
+//! carry differently shaped options, both go through [`CommandPath`].
+
+use std::fmt;
+
+use twilight_model::application::{
+    command::CommandOptionType,
+    interaction::application_command_autocomplete::ApplicationCommandAutocompleteDataOption,
+};
+use zephyrus::twilight_exports::{CommandDataOption, CommandOptionValue};
+
+/// An option that may be a subcommand or subcommand group.
+pub trait PathOption: Sized {
+    fn name(&self) -> &str;
+
+    /// The options nested in a subcommand or subcommand group, `None` for
+    /// any other kind of option.
+    fn nested(&self) -> Option<&[Self]>;
+}
+
+impl PathOption for CommandDataOption {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn nested(&self) -> Option<&[Self]> {
+        match &self.value {
+            CommandOptionValue::SubCommand(options)
+            | CommandOptionValue::SubCommandGroup(options) => Some(options),
+            _ => None,
+        }
+    }
+}
+
+impl PathOption for ApplicationCommandAutocompleteDataOption {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn nested(&self) -> Option<&[Self]> {
+        match self.kind {
+            CommandOptionType::SubCommand | CommandOptionType::SubCommandGroup => {
+                Some(&self.options)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The names leading to the invoked command, e.g. `["verification", "setup"]`,
+/// and the options passed to it.
+pub struct CommandPath<'a, O> {
+    pub names: Vec<&'a str>,
+    pub options: &'a [O],
+}
+
+impl<'a, O: PathOption> CommandPath<'a, O> {
+    pub fn resolve(name: &'a str, options: &'a [O]) -> Self {
+        let mut path = Self {
+            names: vec![name],
+            options,
+        };
+        while let Some((option, nested)) = path
+            .options
+            .first()
+            .and_then(|option| Some((option, option.nested()?)))
+        {
+            path.names.push(option.name());
+            path.options = nested;
+        }
+        path
+    }
+}
+
+/// The space separated names, which is how routers key commands.
+impl<O> fmt::Display for CommandPath<'_, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.names.join(" "))
+    }
+}
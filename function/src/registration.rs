@@ -0,0 +1,621 @@
+//! Works out where application commands are registered and how the local
+//! command tree differs from what Discord has.
+
+use std::{collections::BTreeMap, env};
+
+use serde_json::{json, Map, Value};
+use twilight_http::Client;
+use twilight_model::{
+    application::command::Command as RegisteredCommand,
+    guild::Permissions,
+    id::{
+        marker::{ApplicationMarker, GuildMarker},
+        Id,
+    },
+};
+use zephyrus::{command::Command, group::ParentType, prelude::*};
+
+use crate::{autocomplete::AutocompleteRouter, timeouts, Context};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Scope {
+    Global,
+    Guild(Id<GuildMarker>),
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Scope::Global => f.write_str("global"),
+            Scope::Guild(guild_id) => write!(f, "guild {}", guild_id),
+        }
+    }
+}
+
+/// Scopes to register in. `REGISTER_GLOBAL=true` registers globally and
+/// `DEV_GUILDS` takes a comma separated list of guild ids for development
+/// copies, which update instantly unlike global commands.
+pub fn scopes_from_env() -> Vec<Scope> {
+    let mut scopes = Vec::new();
+
+    if env::var("REGISTER_GLOBAL").map_or(false, |v| v == "1" || v == "true") {
+        scopes.push(Scope::Global);
+    }
+
+    if let Ok(guilds) = env::var("DEV_GUILDS") {
+        scopes.extend(
+            guilds
+                .split(',')
+                .filter_map(|id| id.trim().parse().ok())
+                .filter_map(Id::new_checked)
+                .map(Scope::Guild),
+        );
+    }
+
+    scopes
+}
+
+/// Fields compared between trees, anything else Discord returns (ids,
+/// versions, localizations, the deprecated `default_permission`) is ignored.
+const COMPARED_FIELDS: &[&str] = &[
+    "autocomplete",
+    "channel_types",
+    "choices",
+    "default_member_permissions",
+    "description",
+    "dm_permission",
+    "max_value",
+    "min_value",
+    "name",
+    "options",
+    "required",
+    "type",
+    "value",
+];
+
+/// Strips a command down to the fields in [`COMPARED_FIELDS`], dropping
+/// fields set to their default so absent and default values compare equal.
+pub fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .filter(|(key, _)| COMPARED_FIELDS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), canonicalize(value)))
+                .filter(|(key, value)| !is_default(key, value))
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(canonicalize).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Nulls and empty lists are always defaults. Flags default to `false`,
+/// except `dm_permission` which Discord assumes is `true`.
+fn is_default(key: &str, value: &Value) -> bool {
+    match (key, value) {
+        (_, Value::Null) => true,
+        (_, Value::Array(values)) => values.is_empty(),
+        ("dm_permission", Value::Bool(allowed)) => *allowed,
+        (_, Value::Bool(set)) => !set,
+        _ => false,
+    }
+}
+
+/// Bounds of integer options as `(path, option, min, max)`, the framework
+/// has no way to declare them.
+const OPTION_LIMITS: &[(&str, &str, i64, i64)] = &[(
+    "verification timeout",
+    "minutes",
+    1,
+    timeouts::MAX_MINUTES as i64,
+)];
+
+/// The command's options. Those with an autocomplete provider for `path` are
+/// flagged and those in [`OPTION_LIMITS`] get their bounds, the framework
+/// doesn't know about either.
+fn arguments(
+    path: &str,
+    command: &Command<Context>,
+    autocomplete: &AutocompleteRouter,
+) -> Vec<Value> {
+    command
+        .fun_arguments
+        .iter()
+        .map(|argument| {
+            let mut option = serde_json::to_value(argument.as_option()).unwrap_or(Value::Null);
+            let name = option["name"].as_str().unwrap_or_default().to_string();
+            if autocomplete.has_provider(path, &name) {
+                option["autocomplete"] = Value::Bool(true);
+            }
+            if let Some((_, _, min, max)) = OPTION_LIMITS
+                .iter()
+                .find(|(p, o, _, _)| *p == path && *o == name)
+            {
+                option["min_value"] = json!(min);
+                option["max_value"] = json!(max);
+            }
+            option
+        })
+        .collect()
+}
+
+fn command_value(
+    parent: &str,
+    command: &Command<Context>,
+    autocomplete: &AutocompleteRouter,
+) -> Value {
+    let path = format!("{} {}", parent, command.name);
+    json!({
+        "type": 1,
+        "name": command.name,
+        "description": command.description,
+        "options": arguments(&path, command, autocomplete),
+    })
+}
+
+/// Permissions members need to see the commands. Every command changes or
+/// shows the server's configuration, admins can still grant them to other
+/// roles in the server's integration settings.
+pub const DEFAULT_MEMBER_PERMISSIONS: Permissions = Permissions::MANAGE_GUILD;
+
+/// A top level command wrapping `options`, limited to members with
+/// [`DEFAULT_MEMBER_PERMISSIONS`]. Every command works on the guild it's run
+/// in, so none of them are offered in DMs.
+fn top_level_value(name: &str, description: &str, options: Vec<Value>) -> Value {
+    json!({
+        "type": 1,
+        "name": name,
+        "description": description,
+        "default_member_permissions": DEFAULT_MEMBER_PERMISSIONS.bits().to_string(),
+        "dm_permission": false,
+        "options": options,
+    })
+}
+
+/// The full command tree as registered, keyed by top level command name so
+/// serialising it is stable. Argument order is kept as Discord shows it.
+/// Every entry is canonicalized, see [`canonicalize`].
+pub fn command_tree(
+    framework: &Framework<Context>,
+    autocomplete: &AutocompleteRouter,
+) -> BTreeMap<String, Value> {
+    let mut tree = BTreeMap::new();
+
+    for (name, command) in &framework.commands {
+        tree.insert(
+            name.to_string(),
+            canonicalize(&top_level_value(
+                command.name,
+                command.description,
+                arguments(command.name, command, autocomplete),
+            )),
+        );
+    }
+
+    for (name, group) in &framework.groups {
+        let options: Vec<Value> = match &group.kind {
+            ParentType::Simple(commands) => {
+                let sorted: BTreeMap<_, _> = commands.iter().collect();
+                sorted
+                    .values()
+                    .map(|c| command_value(group.name, c, autocomplete))
+                    .collect()
+            }
+            ParentType::Group(groups) => {
+                let sorted: BTreeMap<_, _> = groups.iter().collect();
+                sorted
+                    .values()
+                    .map(|subgroup| {
+                        let path = format!("{} {}", group.name, subgroup.name);
+                        let commands: BTreeMap<_, _> = subgroup.subcommands.iter().collect();
+                        json!({
+                            "type": 2,
+                            "name": subgroup.name,
+                            "description": subgroup.description,
+                            "options": commands
+                                .values()
+                                .map(|c| command_value(&path, c, autocomplete))
+                                .collect::<Vec<_>>(),
+                        })
+                    })
+                    .collect()
+            }
+        };
+
+        tree.insert(
+            name.to_string(),
+            canonicalize(&top_level_value(group.name, group.description, options)),
+        );
+    }
+
+    tree
+}
+
+/// The command tree as pretty printed JSON, in the shape Discord takes for a
+/// bulk overwrite. Commands are sorted by name and object keys are sorted, so
+/// the output only changes when the commands do and can be checked in.
+pub fn export(framework: &Framework<Context>, autocomplete: &AutocompleteRouter) -> String {
+    let commands: Vec<&Value> = command_tree(framework, autocomplete).values().collect();
+    let mut json = serde_json::to_string_pretty(&commands).expect("command tree is valid JSON");
+    json.push('\n');
+    json
+}
+
+/// Lines of context shown around each change.
+const DIFF_CONTEXT: usize = 2;
+
+/// Top level commands that differ between two trees.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct TreeDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<Change>,
+}
+
+/// A command in both trees, with its canonical JSON on either side.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Change {
+    pub name: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+impl TreeDiff {
+    pub fn new(old: &BTreeMap<String, Value>, new: &BTreeMap<String, Value>) -> Self {
+        let mut diff = TreeDiff::default();
+
+        for (name, value) in new {
+            match old.get(name) {
+                None => diff.added.push(name.clone()),
+                Some(old_value) if old_value != value => diff.changed.push(Change {
+                    name: name.clone(),
+                    old: old_value.clone(),
+                    new: value.clone(),
+                }),
+                _ => {}
+            }
+        }
+        diff.removed = old
+            .keys()
+            .filter(|name| !new.contains_key(*name))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for TreeDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for name in &self.added {
+            writeln!(f, "+ {}", name)?;
+        }
+        for name in &self.removed {
+            writeln!(f, "- {}", name)?;
+        }
+        for change in &self.changed {
+            writeln!(f, "~ {}", change.name)?;
+            write_line_diff(f, &pretty(&change.old), &pretty(&change.new))?;
+        }
+        Ok(())
+    }
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).expect("command tree is valid JSON")
+}
+
+/// Writes the lines that differ between `old` and `new` prefixed with `-` and
+/// `+`, with [`DIFF_CONTEXT`] unchanged lines around them.
+fn write_line_diff(f: &mut std::fmt::Formatter<'_>, old: &str, new: &str) -> std::fmt::Result {
+    let lines = line_diff(
+        &old.lines().collect::<Vec<_>>(),
+        &new.lines().collect::<Vec<_>>(),
+    );
+
+    let mut skipped = false;
+    for (i, (marker, line)) in lines.iter().enumerate() {
+        let near_change = lines
+            [i.saturating_sub(DIFF_CONTEXT)..(i + DIFF_CONTEXT + 1).min(lines.len())]
+            .iter()
+            .any(|(marker, _)| *marker != ' ');
+        if near_change {
+            writeln!(f, "    {} {}", marker, line)?;
+            skipped = false;
+        } else if !skipped {
+            writeln!(f, "      ...")?;
+            skipped = true;
+        }
+    }
+    Ok(())
+}
+
+/// Diffs two lists of lines through their longest common subsequence. The
+/// command JSON is at most a few hundred lines, so the quadratic table is fine.
+fn line_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    // common[i][j] is the LCS length of old[i..] and new[j..].
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            lines.push(('+', new[j]));
+            j += 1;
+        } else {
+            lines.push(('-', old[i]));
+            i += 1;
+        }
+    }
+    lines
+}
+
+/// The commands Discord currently has registered in `scope`.
+pub async fn remote_tree(
+    http: &Client,
+    application_id: Id<ApplicationMarker>,
+    scope: Scope,
+) -> Result<BTreeMap<String, Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let client = http.interaction(application_id);
+    let commands = match scope {
+        Scope::Global => client.global_commands().exec().await?.models().await?,
+        Scope::Guild(guild_id) => {
+            client
+                .guild_commands(guild_id)
+                .exec()
+                .await?
+                .models()
+                .await?
+        }
+    };
+
+    commands
+        .iter()
+        .map(|command| {
+            Ok((
+                command.name.clone(),
+                canonicalize(&serde_json::to_value(command)?),
+            ))
+        })
+        .collect()
+}
+
+/// Overwrites the commands in `scope` with `tree`. The tree is sent rather
+/// than letting the framework register itself, which would leave out
+/// `dm_permission` and the autocomplete flags, so what is registered is
+/// exactly what was diffed.
+pub async fn apply(
+    http: &Client,
+    application_id: Id<ApplicationMarker>,
+    tree: &BTreeMap<String, Value>,
+    scope: Scope,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let commands = tree
+        .values()
+        .map(|command| {
+            // The model requires a version, Discord ignores it on writes.
+            let mut command = command.clone();
+            command["version"] = json!("1");
+            serde_json::from_value::<RegisteredCommand>(command)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let client = http.interaction(application_id);
+    match scope {
+        Scope::Global => {
+            client.set_global_commands(&commands).exec().await?;
+        }
+        Scope::Guild(guild_id) => {
+            client
+                .set_guild_commands(guild_id, &commands)
+                .exec()
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn changed(diff: &TreeDiff) -> Vec<&str> {
+        diff.changed
+            .iter()
+            .map(|change| change.name.as_str())
+            .collect()
+    }
+
+    fn tree(commands: &[Value]) -> BTreeMap<String, Value> {
+        commands
+            .iter()
+            .map(|command| {
+                (
+                    command["name"].as_str().unwrap().to_string(),
+                    canonicalize(command),
+                )
+            })
+            .collect()
+    }
+
+    fn logging(description: &str) -> Value {
+        json!({
+            "type": 1,
+            "name": "logging",
+            "description": description,
+            "dm_permission": false,
+            "options": [{
+                "type": 1,
+                "name": "history",
+                "description": "Show recent configuration changes",
+                "options": [{
+                    "type": 4,
+                    "name": "page",
+                    "description": "The page to show, starting at 1",
+                    "required": false,
+                }],
+            }],
+        })
+    }
+
+    #[test]
+    fn canonicalize_ignores_unknown_fields_and_defaults() {
+        let remote = json!({
+            "id": "1",
+            "application_id": "2",
+            "version": "3",
+            "type": 1,
+            "name": "logging",
+            "description": "Configuration for logging",
+            "description_localizations": null,
+            "dm_permission": true,
+            "options": [{
+                "type": 3,
+                "name": "field",
+                "description": "Only show changes to this setting",
+                "required": false,
+                "autocomplete": false,
+                "choices": [],
+            }],
+        });
+
+        assert_eq!(
+            canonicalize(&remote),
+            json!({
+                "type": 1,
+                "name": "logging",
+                "description": "Configuration for logging",
+                "options": [{
+                    "type": 3,
+                    "name": "field",
+                    "description": "Only show changes to this setting",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn canonicalize_keeps_disabled_dm_permission() {
+        let command = json!({ "name": "logging", "dm_permission": false });
+
+        assert_eq!(canonicalize(&command), command);
+    }
+
+    #[test]
+    fn default_values_compare_equal() {
+        let explicit = tree(&[logging("Configuration for logging")]);
+        let mut implicit = logging("Configuration for logging");
+        implicit["options"][0]["options"][0]
+            .as_object_mut()
+            .unwrap()
+            .remove("required");
+        implicit["options"][0]["choices"] = json!([]);
+
+        assert!(TreeDiff::new(&explicit, &tree(&[implicit])).is_empty());
+    }
+
+    #[test]
+    fn description_edit_is_a_change() {
+        let old = tree(&[logging("Configuration for logging")]);
+        let new = tree(&[logging("Configure logging")]);
+
+        let diff = TreeDiff::new(&old, &new);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(changed(&diff), vec!["logging"]);
+    }
+
+    #[test]
+    fn display_shows_changed_lines() {
+        let old = tree(&[logging("Configuration for logging")]);
+        let new = tree(&[logging("Configure logging")]);
+
+        let shown = TreeDiff::new(&old, &new).to_string();
+        assert!(shown.starts_with("~ logging\n"));
+        assert!(shown.contains("    -   \"description\": \"Configuration for logging\","));
+        assert!(shown.contains("    +   \"description\": \"Configure logging\","));
+        // The subcommand is far enough from the change to be left out.
+        assert!(!shown.contains("history"));
+    }
+
+    #[test]
+    fn line_diff_keeps_common_lines() {
+        assert_eq!(
+            line_diff(&["a", "b", "c"], &["a", "x", "c", "d"]),
+            vec![(' ', "a"), ('+', "x"), ('-', "b"), (' ', "c"), ('+', "d")]
+        );
+    }
+
+    #[test]
+    fn rename_is_a_removal_and_an_addition() {
+        let old = tree(&[logging("Configuration for logging")]);
+        let mut renamed = logging("Configuration for logging");
+        renamed["name"] = json!("logs");
+        let new = tree(&[renamed]);
+
+        assert_eq!(
+            TreeDiff::new(&old, &new),
+            TreeDiff {
+                added: vec![String::from("logs")],
+                removed: vec![String::from("logging")],
+                ..TreeDiff::default()
+            }
+        );
+    }
+
+    #[test]
+    fn option_rename_is_a_change() {
+        let old = tree(&[logging("Configuration for logging")]);
+        let mut renamed = logging("Configuration for logging");
+        renamed["options"][0]["options"][0]["name"] = json!("number");
+        let new = tree(&[renamed]);
+
+        assert_eq!(changed(&TreeDiff::new(&old, &new)), vec!["logging"]);
+    }
+
+    #[test]
+    fn enabling_dms_is_a_change() {
+        let old = tree(&[logging("Configuration for logging")]);
+        let mut in_dms = logging("Configuration for logging");
+        in_dms["dm_permission"] = json!(true);
+
+        assert_eq!(
+            changed(&TreeDiff::new(&old, &tree(&[in_dms]))),
+            vec!["logging"]
+        );
+    }
+
+    #[test]
+    fn export_matches_checked_in_tree() {
+        let framework = crate::framework(
+            Arc::new(Client::new(String::new())),
+            Id::new(1),
+            Context::new("redis://localhost:6379", Id::new(1)),
+        );
+
+        assert_eq!(
+            export(&framework, &crate::components().autocomplete),
+            include_str!("../commands.json"),
+            "commands.json is out of date, regenerate it with `cargo run --bin register-commands -- export > commands.json`"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Hands out and takes away the roles verification is configured with.
+
+use deadpool_redis::{redis::RedisError, Connection};
+use twilight_http::{
+    api_error::{ApiError, GeneralApiError},
+    error::ErrorType,
+    Client,
+};
+use twilight_model::{
+    guild::Permissions,
+    id::{
+        marker::{GuildMarker, RoleMarker, UserMarker},
+        Id,
+    },
+};
+
+use crate::{
+    cache::{self, CacheError},
+    config::GuildConfig,
+};
+
+/// Discord's error code for requests the bot lacks the permissions for.
+const MISSING_PERMISSIONS: u64 = 50013;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoleError {
+    #[error("This server has no verification role set, ask an admin to run `/verification role`.")]
+    NotConfigured,
+
+    #[error("I need the Manage Roles permission to give out <@&{0}>.")]
+    MissingManageRoles(Id<RoleMarker>),
+
+    #[error("<@&{0}> is above my highest role, an admin needs to move my role above it.")]
+    AboveBot(Id<RoleMarker>),
+
+    #[error("Discord refused to update the member's roles.")]
+    RequestFailed(#[from] twilight_http::Error),
+
+    #[error("Failed to read the verification config.")]
+    RedisFailed(#[from] RedisError),
+
+    #[error("Failed to read the guild cache.")]
+    CacheFailed(#[from] CacheError),
+}
+
+pub struct RoleService<'a> {
+    http: &'a Client,
+    bot_id: Id<UserMarker>,
+}
+
+impl<'a> RoleService<'a> {
+    pub fn new(http: &'a Client, bot_id: Id<UserMarker>) -> Self {
+        Self { http, bot_id }
+    }
+
+    /// Gives a member the verification role and removes the unverified role
+    /// when one is configured.
+    pub async fn grant_verified(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<(), RoleError> {
+        let config = GuildConfig::load(conn, guild_id).await?;
+        let role = config.verification_role.ok_or(RoleError::NotConfigured)?;
+
+        self.add(conn, guild_id, user_id, role).await?;
+        if let Some(unverified) = config.unverified_role {
+            self.remove(conn, guild_id, user_id, unverified).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes the verification role away again, putting the unverified role
+    /// back when one is configured.
+    pub async fn revoke_verified(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<(), RoleError> {
+        let config = GuildConfig::load(conn, guild_id).await?;
+        let role = config.verification_role.ok_or(RoleError::NotConfigured)?;
+
+        self.remove(conn, guild_id, user_id, role).await?;
+        if let Some(unverified) = config.unverified_role {
+            self.add(conn, guild_id, user_id, unverified).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn add(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        role_id: Id<RoleMarker>,
+    ) -> Result<(), RoleError> {
+        match self
+            .http
+            .add_guild_member_role(guild_id, user_id, role_id)
+            .exec()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(why) => Err(self.explain(conn, guild_id, role_id, why).await),
+        }
+    }
+
+    pub async fn remove(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        role_id: Id<RoleMarker>,
+    ) -> Result<(), RoleError> {
+        match self
+            .http
+            .remove_guild_member_role(guild_id, user_id, role_id)
+            .exec()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(why) => Err(self.explain(conn, guild_id, role_id, why).await),
+        }
+    }
+
+    /// Discord answers both a missing permission and a role above the bot
+    /// with the same error, so the cache is used to tell them apart.
+    async fn explain(
+        &self,
+        conn: &mut Connection,
+        guild_id: Id<GuildMarker>,
+        role_id: Id<RoleMarker>,
+        why: twilight_http::Error,
+    ) -> RoleError {
+        let missing_permissions = matches!(
+            why.kind(),
+            ErrorType::Response {
+                error: ApiError::General(GeneralApiError {
+                    code: MISSING_PERMISSIONS,
+                    ..
+                }),
+                ..
+            }
+        );
+        if !missing_permissions {
+            return RoleError::RequestFailed(why);
+        }
+
+        match cache::permissions(conn, guild_id, self.bot_id).await {
+            Ok(Some(permissions))
+                if !permissions.intersects(Permissions::MANAGE_ROLES | Permissions::ADMINISTRATOR) =>
+            {
+                return RoleError::MissingManageRoles(role_id)
+            }
+            Err(err) => return RoleError::CacheFailed(err),
+            _ => {}
+        }
+
+        match (
+            cache::highest_position(conn, guild_id, self.bot_id).await,
+            cache::role(conn, guild_id, role_id).await,
+        ) {
+            (Ok(Some(highest)), Ok(Some(role))) if role.position >= highest => {
+                RoleError::AboveBot(role_id)
+            }
+            _ => RoleError::RequestFailed(why),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Serves interactions over plain HTTP for deployments outside of Lambda.
+
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body as HyperBody, Request as HyperRequest, Response as HyperResponse, Server,
+};
+use lambda_http::{Body, Error, Request};
+use zephyrus::prelude::Framework;
+
+use crate::{components::Components, Context};
+
+pub async fn run(
+    framework: Arc<Framework<Context>>,
+    components: Arc<Components>,
+    port: u16,
+) -> Result<(), Error> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    let make_service = make_service_fn(move |_| {
+        let framework = Arc::clone(&framework);
+        let components = Arc::clone(&components);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle(request, Arc::clone(&framework), Arc::clone(&components))
+            }))
+        }
+    });
+
+    tracing::info!(%addr, "listening for interactions");
+    Server::bind(&addr).serve(make_service).await?;
+    Ok(())
+}
+
+/// Converts between hyper and `lambda_http` types around the shared handler.
+async fn handle(
+    request: HyperRequest<HyperBody>,
+    framework: Arc<Framework<Context>>,
+    components: Arc<Components>,
+) -> Result<HyperResponse<HyperBody>, Infallible> {
+    let (parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => match String::from_utf8(bytes.to_vec()) {
+            Ok(text) => Body::Text(text),
+            Err(_) => Body::Binary(bytes.to_vec()),
+        },
+        Err(why) => {
+            tracing::warn!("failed to read request body: {}", why);
+            return Ok(HyperResponse::builder()
+                .status(400)
+                .body(HyperBody::empty())
+                .expect("static response parts are valid"));
+        }
+    };
+
+    let response = match crate::handler::function_handler(
+        Request::from_parts(parts, body),
+        framework,
+        components,
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(why) => {
+            tracing::error!("unhandled error: {}", why);
+            return Ok(HyperResponse::builder()
+                .status(500)
+                .body(HyperBody::empty())
+                .expect("static response parts are valid"));
+        }
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = match body {
+        Body::Empty => HyperBody::empty(),
+        Body::Text(text) => HyperBody::from(text),
+        Body::Binary(bytes) => HyperBody::from(bytes),
+    };
+    Ok(HyperResponse::from_parts(parts, body))
+}
@@ -0,0 +1,78 @@
+//! Schedules members who have to verify before a deadline. Deadlines live in
+//! the `timeouts` sorted set, scored by when they expire, and are processed
+//! by the worker.
+
+use deadpool_redis::{
+    redis::{AsyncCommands, RedisError},
+    Connection,
+};
+use twilight_model::id::{
+    marker::{GuildMarker, UserMarker},
+    Id,
+};
+
+const KEY: &str = "timeouts";
+
+/// How many expired deadlines are claimed per poll.
+pub const BATCH_SIZE: isize = 50;
+
+/// Longest timeout `/verification timeout` accepts, a week in minutes.
+pub const MAX_MINUTES: u64 = 7 * 24 * 60;
+
+/// Seconds until a deadline that failed to apply is tried again.
+pub const RETRY_DELAY: u64 = 60;
+
+fn member(guild_id: Id<GuildMarker>, user_id: Id<UserMarker>) -> String {
+    format!("{}:{}", guild_id.get(), user_id.get())
+}
+
+fn parse(member: &str) -> Option<(Id<GuildMarker>, Id<UserMarker>)> {
+    let (guild_id, user_id) = member.split_once(':')?;
+    Some((
+        Id::new_checked(guild_id.parse().ok()?)?,
+        Id::new_checked(user_id.parse().ok()?)?,
+    ))
+}
+
+/// Sets the member's deadline to `due`, a Unix timestamp in seconds,
+/// replacing any earlier one.
+pub async fn schedule(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    due: u64,
+) -> Result<(), RedisError> {
+    conn.zadd(KEY, member(guild_id, user_id), due).await
+}
+
+/// Drops the member's deadline, once they verified or left.
+pub async fn cancel(
+    conn: &mut Connection,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<(), RedisError> {
+    conn.zrem(KEY, member(guild_id, user_id)).await
+}
+
+/// Claims deadlines that expired by `now`. A deadline is only returned to
+/// the caller that removed it, so several workers can poll at once. Callers
+/// [`schedule`] it again if applying it fails.
+pub async fn claim_expired(
+    conn: &mut Connection,
+    now: u64,
+) -> Result<Vec<(Id<GuildMarker>, Id<UserMarker>)>, RedisError> {
+    let expired: Vec<String> = conn.zrangebyscore_limit(KEY, 0, now, 0, BATCH_SIZE).await?;
+
+    let mut claimed = Vec::new();
+    for entry in expired {
+        let removed: usize = conn.zrem(KEY, &entry).await?;
+        if removed == 0 {
+            continue;
+        }
+        match parse(&entry) {
+            Some(ids) => claimed.push(ids),
+            None => tracing::warn!(entry = %entry, "dropped malformed timeout"),
+        }
+    }
+    Ok(claimed)
+}
@@ -1,5 +1,21 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use deadpool_redis::{
+    redis::{self, RedisError},
+    Connection,
+};
 use ed25519_dalek::{PublicKey, Signature, SignatureError, Verifier};
 use hex::FromHexError;
+use lazy_static::lazy_static;
+use twilight_model::id::{marker::InteractionMarker, Id};
+
+lazy_static! {
+    /// How far in seconds a request's timestamp may be from our clock.
+    static ref MAX_SKEW: u64 = std::env::var("MAX_TIMESTAMP_SKEW")
+        .ok()
+        .and_then(|skew| skew.parse().ok())
+        .unwrap_or(300);
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum VerificationError {
@@ -11,26 +27,223 @@ pub enum VerificationError {
 
     #[error("Invalid signature provided.")]
     InvalidSignature(ed25519_dalek::ed25519::Error),
+
+    #[error("No public keys configured, set PUBLIC_KEYS.")]
+    NoPublicKeys,
+
+    #[error("The signature does not match any application.")]
+    NoMatchingKey,
+
+    #[error("Invalid timestamp provided.")]
+    InvalidTimestamp,
+
+    #[error("Request timestamp is {0} seconds away from the current time.")]
+    StaleTimestamp(u64),
+
+    #[error("Interaction {0} has already been handled.")]
+    Replayed(Id<InteractionMarker>),
+
+    #[error("Failed to record the interaction id.")]
+    RedisFailed(#[from] RedisError),
+}
+
+/// A public key of one of the applications this deployment serves.
+#[derive(Debug)]
+pub struct ApplicationKey {
+    /// Label used when reporting which application a request belongs to.
+    pub name: String,
+    pub key: PublicKey,
+}
+
+impl ApplicationKey {
+    /// Parses `name=hex`, or a bare hex key which is then named after its
+    /// position in the list.
+    fn parse(entry: &str, index: usize) -> Result<Self, VerificationError> {
+        let (name, hex_key) = match entry.split_once('=') {
+            Some((name, hex_key)) => (name.trim().to_string(), hex_key.trim()),
+            None => (index.to_string(), entry.trim()),
+        };
+        let key = hex::decode(hex_key)
+            .map_err(VerificationError::ParseHexFailed)
+            .and_then(|bytes| PublicKey::from_bytes(&bytes).map_err(VerificationError::InvalidPublicKey))?;
+
+        Ok(Self { name, key })
+    }
+}
+
+/// Reads the comma separated `PUBLIC_KEYS`, falling back to the single
+/// `PUBLIC_KEY` older deployments set.
+pub fn keys_from_env() -> Result<Vec<ApplicationKey>, VerificationError> {
+    let keys = std::env::var("PUBLIC_KEYS")
+        .or_else(|_| std::env::var("PUBLIC_KEY"))
+        .map_err(|_| VerificationError::NoPublicKeys)?;
+
+    let keys = keys
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .enumerate()
+        .map(|(index, entry)| ApplicationKey::parse(entry, index))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Err(VerificationError::NoPublicKeys);
+    }
+    Ok(keys)
 }
 
-pub fn verify_signature(
-    public_key: &str,
+/// Checks the signature against every key, returning the one that matched.
+pub fn verify_signature<'a>(
+    keys: &'a [ApplicationKey],
     signature: &str,
     timestamp: &str,
     body: &str,
-) -> Result<(), VerificationError> {
-    let public_key = &hex::decode(public_key)
+) -> Result<&'a ApplicationKey, VerificationError> {
+    let signature = hex::decode(&signature)
         .map_err(VerificationError::ParseHexFailed)
         .and_then(|bytes| {
-            PublicKey::from_bytes(&bytes).map_err(VerificationError::InvalidSignature)
+            Signature::from_bytes(&bytes).map_err(VerificationError::InvalidSignature)
         })?;
+    let message = format!("{}{}", timestamp, body);
 
-    Ok(public_key.verify(
-        format!("{}{}", timestamp, body).as_bytes(),
-        &hex::decode(&signature)
-            .map_err(VerificationError::ParseHexFailed)
-            .and_then(|bytes| {
-                Signature::from_bytes(&bytes).map_err(VerificationError::InvalidSignature)
-            })?,
-    )?)
+    keys.iter()
+        .find(|key| key.key.verify(message.as_bytes(), &signature).is_ok())
+        .ok_or(VerificationError::NoMatchingKey)
+}
+
+/// Rejects requests signed too long ago, or too far in the future.
+pub fn verify_timestamp(timestamp: &str) -> Result<(), VerificationError> {
+    let timestamp: u64 = timestamp
+        .parse()
+        .map_err(|_| VerificationError::InvalidTimestamp)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| VerificationError::InvalidTimestamp)?
+        .as_secs();
+
+    let skew = now.abs_diff(timestamp);
+    if skew > *MAX_SKEW {
+        return Err(VerificationError::StaleTimestamp(skew));
+    }
+
+    Ok(())
+}
+
+/// Records the interaction id, failing if it was seen before. Ids are kept for
+/// twice the allowed skew, after which the timestamp check rejects the request.
+pub async fn verify_unseen(
+    conn: &mut Connection,
+    interaction_id: Id<InteractionMarker>,
+) -> Result<(), VerificationError> {
+    let set: Option<String> = redis::cmd("SET")
+        .arg(format!("interaction:{}", interaction_id.get()))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(*MAX_SKEW * 2)
+        .query_async(conn)
+        .await?;
+
+    match set {
+        Some(_) => Ok(()),
+        None => Err(VerificationError::Replayed(interaction_id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+    use super::*;
+
+    fn keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn application(name: &str, seed: u8) -> ApplicationKey {
+        ApplicationKey {
+            name: name.to_string(),
+            key: keypair(seed).public,
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn accepts_current_timestamp() {
+        assert!(verify_timestamp(&now().to_string()).is_ok());
+    }
+
+    #[test]
+    fn rejects_stale_and_future_timestamps() {
+        let skew = *MAX_SKEW + 60;
+
+        assert!(matches!(
+            verify_timestamp(&(now() - skew).to_string()),
+            Err(VerificationError::StaleTimestamp(_))
+        ));
+        assert!(matches!(
+            verify_timestamp(&(now() + skew).to_string()),
+            Err(VerificationError::StaleTimestamp(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_unparsable_timestamp() {
+        for timestamp in ["", "soon", "-5", "1.5"] {
+            assert!(matches!(
+                verify_timestamp(timestamp),
+                Err(VerificationError::InvalidTimestamp)
+            ));
+        }
+    }
+
+    #[test]
+    fn signature_matching_second_key_names_it() {
+        let keys = [application("main", 1), application("beta", 2)];
+        let signature = keypair(2).sign(b"1700000000{}");
+
+        let key = verify_signature(&keys, &hex::encode(signature), "1700000000", "{}").unwrap();
+        assert_eq!(key.name, "beta");
+    }
+
+    #[test]
+    fn signature_matching_no_key_is_rejected() {
+        let keys = [application("main", 1)];
+        let signature = keypair(3).sign(b"1700000000{}");
+
+        assert!(matches!(
+            verify_signature(&keys, &hex::encode(signature), "1700000000", "{}"),
+            Err(VerificationError::NoMatchingKey)
+        ));
+    }
+
+    #[test]
+    fn parses_named_and_bare_keys() {
+        let hex_key = hex::encode(keypair(1).public);
+
+        let named = ApplicationKey::parse(&format!(" main = {} ", hex_key), 0).unwrap();
+        assert_eq!(named.name, "main");
+        assert_eq!(named.key, keypair(1).public);
+
+        let bare = ApplicationKey::parse(&hex_key, 2).unwrap();
+        assert_eq!(bare.name, "2");
+        assert_eq!(bare.key, keypair(1).public);
+    }
+
+    #[test]
+    fn empty_key_list_is_rejected() {
+        std::env::set_var("PUBLIC_KEYS", " , ");
+
+        assert!(matches!(
+            keys_from_env(),
+            Err(VerificationError::NoPublicKeys)
+        ));
+    }
 }